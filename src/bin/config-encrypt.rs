@@ -0,0 +1,56 @@
+//! Encrypt a value for pasting into a configuration file, see `config::encrypted`.
+//!
+//! The value is read from standard input, so it is not left in the shell's history. `--key` is the
+//! dotted key of the item the value is for, e.g. `database.password`; the value cannot be
+//! decrypted for any other item.
+
+use std::env;
+use std::io::Read;
+use std::process;
+
+use config::encrypted::Aead;
+
+const USAGE: &str = "\
+usage: config-encrypt --generate-key
+       config-encrypt --key-file <path> --key <name> < value
+       config-encrypt --key-env <variable> --key <name> < value";
+
+fn fail(message: &str) -> ! {
+  eprintln!("config-encrypt: {}", message);
+  process::exit(2);
+}
+
+fn usage() -> ! {
+  eprintln!("{}", USAGE);
+  process::exit(2);
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+  let (args, key) = match args.as_slice() {
+    ["--generate-key"] => {
+      println!("{}", Aead::generate_key());
+      return;
+    }
+    [args @ .., "--key", key] => (args, *key),
+    _ => usage(),
+  };
+
+  let aead = match args {
+    ["--key-file", path] => Aead::from_file(path),
+    ["--key-env", var] => match env::var(var) {
+      Ok(key) => Aead::from_key(&key),
+      Err(_) => fail(&format!("environment variable ({}) is not set", var)),
+    },
+    _ => usage(),
+  };
+  let aead = aead.unwrap_or_else(|e| fail(&e.to_string()));
+
+  let mut value = String::new();
+  if let Err(e) = std::io::stdin().read_to_string(&mut value) {
+    fail(&format!("could not read the value: {}", e));
+  }
+  println!("{}", aead.encrypt(key, value.trim_end_matches(['\n', '\r'])));
+}
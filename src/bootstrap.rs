@@ -0,0 +1,213 @@
+//! Staged resolution, for configuration that decides where the rest of the configuration is.
+//!
+//! The bootstrap items, `Config` (`--config` or `APPNAME_CONFIG`) and `profile::Profile`, are
+//! resolved first, usually from the environment and the command line. Their values then choose the
+//! configuration files used by the main strategy: the file named by `Config` if given, otherwise
+//! the files found by `discovery::Discovery`, each preceded by the layers for the chosen profile.
+
+use std::path::PathBuf;
+
+use crate::config;
+use crate::constraint;
+use crate::config_file::ConfigurationFile;
+use crate::config_file::LoadError;
+use crate::discovery::Discovered;
+use crate::discovery::Discovery;
+use crate::profile;
+use crate::ConfigurationStrategy;
+use crate::ConfigurationValueSource;
+use crate::Outcome;
+
+config!(
+  /// The configuration file to use, instead of looking for one.
+  (Config PathBuf)
+);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+  #[error("Configuration file from {from} was not accepted: {reason}")]
+  ConfigNotAccepted {
+    from: String,
+    reason: String,
+  },
+  #[error("Configuration file named by {from} could not be loaded: {source}")]
+  ConfigNotLoaded {
+    from: String,
+    source: LoadError,
+  },
+  #[error(transparent)]
+  Profile(#[from] profile::Error),
+}
+
+/// The result of the bootstrap stage: the configuration files to use, and how they were chosen.
+#[derive(Debug)]
+pub struct Bootstrap {
+  config: Option<PathBuf>,
+  config_report: String,
+  profile: profile::Selection,
+  discovered: Option<Discovered>,
+  // each file, preceded by the layers for the profile
+  files: Vec<(Vec<profile::Layer<ConfigurationFile>>, ConfigurationFile)>,
+}
+
+impl Bootstrap {
+  /// Resolve the bootstrap items using these sources, e.g. the command line and the environment,
+  /// then load the configuration file they name, or discover files with `discovery`.
+  /// Unlike discovered files, a file named by `Config` must exist and be valid, and a profile that
+  /// is given must be valid.
+  pub fn resolve(sources: &[&dyn ConfigurationValueSource], discovery: &Discovery) -> Result<Self, Error> {
+    let strategy = ConfigurationStrategy::new(sources.to_vec());
+    let mut config = Config::new(None);
+    let (config_from, config_report) = {
+      let attempts = strategy.try_get(&mut config);
+      if let Some(invalid) = attempts.attempts().iter().find(|a| a.outcome() == Outcome::Invalid) {
+        return Err(Error::ConfigNotAccepted {
+          from: invalid.source().describe(attempts.item()),
+          reason: invalid.error().and_then(constraint::find_invalid).map(|e| e.to_string()).unwrap_or_default(),
+        });
+      }
+      (attempts.provenance(), attempts.report())
+    };
+    let profile = profile::select(sources)?;
+
+    let (discovered, bases) = match (config.get(), config_from) {
+      (Some(path), Some(from)) => {
+        let file = ConfigurationFile::new(path);
+        if let Some(e) = file.load_error() {
+          return Err(Error::ConfigNotLoaded { from: from, source: e.clone() });
+        }
+        (None, vec![file])
+      }
+      _ => {
+        let discovered = discovery.discover();
+        let files = discovered.sources();
+        (Some(discovered), files)
+      }
+    };
+
+    let files = bases.into_iter()
+      .map(|base| (profile::overlays(&profile, &base), base))
+      .collect();
+
+    Ok(Self {
+      config: config.get().cloned(),
+      config_report: config_report,
+      profile: profile,
+      discovered: discovered,
+      files: files,
+    })
+  }
+
+  /// Return the configuration file named by `--config` or `APPNAME_CONFIG`, if any.
+  pub fn config(&self) -> Option<&PathBuf> {
+    self.config.as_ref()
+  }
+
+  pub fn profile(&self) -> &profile::Selection {
+    &self.profile
+  }
+
+  /// Return the sources for the configuration files, highest priority first, to be consulted
+  /// after the environment and the command line.
+  pub fn sources(&self) -> Vec<&dyn ConfigurationValueSource> {
+    let mut sources: Vec<&dyn ConfigurationValueSource> = Vec::new();
+    for (layers, base) in self.files.iter() {
+      sources.extend(layers.iter().map(|l| l as &dyn ConfigurationValueSource));
+      sources.push(base);
+    }
+    sources
+  }
+
+  /// Explain both stages: how the bootstrap items were resolved, and which files were chosen.
+  pub fn report(&self) -> String {
+    let mut out = String::from("bootstrap:\n");
+    for line in self.config_report.lines().chain(self.profile.report().lines()) {
+      out.push_str(&format!("  {}\n", line));
+    }
+
+    match &self.discovered {
+      Some(discovered) => for line in discovered.report().lines() {
+        out.push_str(&format!("  {}\n", line));
+      },
+      None => out.push_str("  configuration files: named by config\n"),
+    }
+
+    out.push_str("  layers:\n");
+    for (layers, base) in self.files.iter() {
+      for layer in layers.iter() {
+        out.push_str(&format!("    profile {}: {}{}\n", layer.profile(), layer.source().describe_file(), load_note(layer.source())));
+      }
+      out.push_str(&format!("    {}{}\n", base.describe_file(), load_note(base)));
+    }
+    if self.files.is_empty() {
+      out.push_str("    no configuration files\n");
+    }
+    out
+  }
+}
+
+fn load_note(file: &ConfigurationFile) -> String {
+  match file.load_error() {
+    Some(e) => format!(" (not loaded: {})", e),
+    None => String::new(),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::command_line::CommandLine;
+  use crate::environment::Environment;
+  use crate::testing::TempDir;
+
+  config!(
+    (Port u16)
+  );
+
+  #[test]
+  fn config_option_selects_the_file() {
+    let dir = TempDir::new("bootstrap");
+    std::fs::write(dir.join("app.toml"), "port = 80\n[profile.prod]\nport = 443\n").unwrap();
+
+    let cli = CommandLine::from_args(vec!["prog".into(), "--config".into(), dir.join("app.toml").to_string_lossy().into()]);
+    let env = Environment::new("APPNAME".into(), vec![("APPNAME_PROFILE".into(), Ok("prod".into()))]);
+    let bootstrap = Bootstrap::resolve(&[&cli, &env], &Discovery::new("app")).unwrap();
+    assert_eq!(bootstrap.config(), Some(&dir.join("app.toml")));
+
+    let mut sources: Vec<&dyn ConfigurationValueSource> = vec![&cli, &env];
+    sources.extend(bootstrap.sources());
+    let strategy = ConfigurationStrategy::new(sources);
+    let mut port = Port::new(None);
+    let res = strategy.try_get(&mut port);
+    assert_eq!(res.provenance().unwrap(), format!("profile prod: file {} key profile.prod.port", dir.join("app.toml").display()));
+    drop(res);
+    assert_eq!(port.get(), Some(&443));
+
+    let report = bootstrap.report().replace(&dir.display().to_string(), "DIR");
+    assert_eq!(report, concat!(
+      "bootstrap:\n",
+      "  config:\n",
+      "    command line --config: accepted\n",
+      "  profile:\n",
+      "    command line --profile: not used: Flag (--profile) was not provided.\n",
+      "    env APPNAME_PROFILE: accepted\n",
+      "  configuration files: named by config\n",
+      "  layers:\n",
+      "    profile prod: file DIR/app.prod.toml (not loaded: Could not read configuration file (DIR/app.prod.toml): could not open file: No such file or directory (os error 2).)\n",
+      "    profile prod: file DIR/app.toml section profile.prod\n",
+      "    file DIR/app.toml\n",
+    ));
+  }
+
+  #[test]
+  fn missing_config_file_is_an_error() {
+    let cli = CommandLine::from_args(vec!["prog".into(), "--config".into(), "/nonexistent/app.toml".into()]);
+    let e = Bootstrap::resolve(&[&cli], &Discovery::new("app")).unwrap_err();
+    assert_eq!(e.to_string(), "Configuration file named by command line --config could not be loaded: Could not read configuration file (/nonexistent/app.toml): could not open file: No such file or directory (os error 2).");
+
+    let env = Environment::new("APPNAME".into(), vec![("APPNAME_PROFILE".into(), Ok("a b".into()))]);
+    let e = Bootstrap::resolve(&[&env], &Discovery::new("app")).unwrap_err();
+    assert!(matches!(e, Error::Profile(_)));
+  }
+}
@@ -0,0 +1,198 @@
+//! Items whose values are one of a fixed set of choices, declared with `config_enum!`.
+//!
+//! Each variant is written in kebab-case (`QuadraticBezier` is `quadratic-bezier`), matched
+//! ignoring case and how words are separated (`QuadraticBezier`, `quadratic_bezier` and
+//! `QUADRATIC-BEZIER` all match), and may have aliases.
+
+use convert_case::Casing;
+use convert_case::Case::Kebab;
+
+/// Declare an enum that can be used as the type of a configuration item.
+///
+/// ```ignore
+/// config_enum!(
+///   /// How splines are reticulated.
+///   pub enum SplineReticulationAlgorithm {
+///     Old,
+///     #[alias = "latest"]
+///     New,
+///   }
+/// );
+/// ```
+///
+/// Variants may be documented, and may have any number of `#[alias = "..."]` attributes.
+#[macro_export]
+macro_rules! config_enum {
+  (@munch [$($outer:tt)*] $vis:vis $name:ident {$($out:tt)*} [$(($variant:ident, [$($alias:expr),*]))*] [] []) => {
+    $($outer)*
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    $vis enum $name { $($out)* }
+
+    impl $name {
+      /// Return every variant, with the name and aliases it can be written as.
+      pub fn variants() -> Vec<$crate::choice::Variant<Self>> {
+        vec![$($crate::choice::Variant::new(Self::$variant, stringify!($variant), vec![$($alias),*])),*]
+      }
+
+      /// Return the name this variant is written as, e.g. `quadratic-bezier`.
+      pub fn name(&self) -> String {
+        match self {
+          $(Self::$variant => $crate::choice::canonical(stringify!($variant)),)*
+        }
+      }
+    }
+
+    impl std::fmt::Display for $name {
+      fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.name())
+      }
+    }
+
+    impl $crate::value::ConfigurationValue for $name {
+      fn kind() -> $crate::value::Kind {
+        $crate::value::Kind::Enum(Self::variants().iter().map(|v| v.name()).collect())
+      }
+
+      fn to_value(&self) -> $crate::value::Value {
+        $crate::value::Value::String(self.name())
+      }
+
+      fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        $crate::choice::parse(s, &Self::variants()).map_err(|e| Box::new(e) as Box<dyn std::error::Error>)
+      }
+    }
+  };
+  // an alias for the variant that follows
+  (@munch $outer:tt $vis:vis $name:ident {$($out:tt)*} [$($table:tt)*] [$($attrs:tt)*] [$($aliases:expr),*] #[alias = $a:expr] $($rest:tt)*) => {
+    $crate::config_enum!(@munch $outer $vis $name {$($out)*} [$($table)*] [$($attrs)*] [$($aliases,)* $a] $($rest)*);
+  };
+  // other attributes, such as doc comments, are kept
+  (@munch $outer:tt $vis:vis $name:ident {$($out:tt)*} [$($table:tt)*] [$($attrs:tt)*] [$($aliases:expr),*] #[$attr:meta] $($rest:tt)*) => {
+    $crate::config_enum!(@munch $outer $vis $name {$($out)*} [$($table)*] [$($attrs)* #[$attr]] [$($aliases),*] $($rest)*);
+  };
+  (@munch $outer:tt $vis:vis $name:ident {$($out:tt)*} [$($table:tt)*] [$($attrs:tt)*] [$($aliases:expr),*] $variant:ident $(, $($rest:tt)*)?) => {
+    $crate::config_enum!(@munch $outer $vis $name {$($out)* $($attrs)* $variant,} [$($table)* ($variant, [$($aliases),*])] [] [] $($($rest)*)?);
+  };
+  ($(#[$outer:meta])* $vis:vis enum $name:ident { $($body:tt)* }) => {
+    $crate::config_enum!(@munch [$(#[$outer])*] $vis $name {} [] [] [] $($body)*);
+  };
+}
+
+/// A variant of an enum declared with `config_enum!`.
+#[derive(Debug, Clone)]
+pub struct Variant<T> {
+  value: T,
+  name: &'static str,
+  aliases: Vec<&'static str>,
+}
+
+impl<T: Copy> Variant<T> {
+  pub fn new(value: T, name: &'static str, aliases: Vec<&'static str>) -> Self {
+    Self { value: value, name: name, aliases: aliases }
+  }
+
+  /// Return the name the variant is written as.
+  pub fn name(&self) -> String {
+    canonical(self.name)
+  }
+
+  pub fn aliases(&self) -> &[&'static str] {
+    &self.aliases
+  }
+
+  fn matches(&self, input: &str) -> bool {
+    let input = normalise(input);
+    normalise(&self.name()) == input || self.aliases.iter().any(|a| normalise(a) == input)
+  }
+}
+
+/// Return the name a variant is written as, e.g. `quadratic-bezier` for `QuadraticBezier`.
+pub fn canonical(variant: &str) -> String {
+  variant.to_case(Kebab)
+}
+
+// words are split at case boundaries as well as at `_`, `-` and spaces, so that every spelling
+// of the name becomes the same kebab-case
+fn normalise(s: &str) -> String {
+  s.trim().to_case(Kebab)
+}
+
+/// Describes why a value is not one of the choices.
+#[derive(thiserror::Error, Debug)]
+#[error("{input:?} is not one of the choices: {}.", .choices.join(", "))]
+pub struct UnknownChoice {
+  pub input: String,
+  pub choices: Vec<String>,
+}
+
+/// Find the variant named by `input`, ignoring case.
+pub fn parse<T: Copy>(input: &str, variants: &[Variant<T>]) -> Result<T, UnknownChoice> {
+  match variants.iter().find(|v| v.matches(input)) {
+    Some(v) => Ok(v.value),
+    None => Err(UnknownChoice {
+      input: input.into(),
+      choices: variants.iter().map(|v| v.name()).collect(),
+    }),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::config;
+  use crate::ConfigurationValueSource;
+  use crate::value::ConfigurationValue;
+  use crate::value::Kind;
+
+  config_enum!(
+    /// How splines are reticulated.
+    pub enum SplineReticulationAlgorithm {
+      /// The original algorithm.
+      Old,
+      #[alias = "latest"]
+      #[alias = "v2"]
+      QuadraticBezier,
+    }
+  );
+
+  #[test]
+  fn variants_match_ignoring_case_and_aliases() {
+    use SplineReticulationAlgorithm::*;
+    assert_eq!(SplineReticulationAlgorithm::parse("old").unwrap(), Old);
+    assert_eq!(SplineReticulationAlgorithm::parse("OLD").unwrap(), Old);
+    assert_eq!(SplineReticulationAlgorithm::parse("quadratic_bezier").unwrap(), QuadraticBezier);
+    assert_eq!(SplineReticulationAlgorithm::parse("QuadraticBezier").unwrap(), QuadraticBezier);
+    assert_eq!(SplineReticulationAlgorithm::parse("QUADRATIC-BEZIER").unwrap(), QuadraticBezier);
+    assert_eq!(SplineReticulationAlgorithm::parse("Latest").unwrap(), QuadraticBezier);
+    assert_eq!(QuadraticBezier.to_string(), "quadratic-bezier");
+  }
+
+  #[test]
+  fn errors_list_the_choices() {
+    let e = SplineReticulationAlgorithm::parse("new").unwrap_err();
+    assert_eq!(e.to_string(), "\"new\" is not one of the choices: old, quadratic-bezier.");
+    assert_eq!(SplineReticulationAlgorithm::kind(), Kind::Enum(vec!["old".into(), "quadratic-bezier".into()]));
+  }
+
+  config!(
+    #[default = SplineReticulationAlgorithm::Old]
+    (Reticulation SplineReticulationAlgorithm)
+  );
+
+  #[test]
+  fn enum_items() {
+    let env = crate::environment::Environment::new("APPNAME".into(), vec![("APPNAME_RETICULATION".into(), Ok("V2".into()))]);
+    let mut ci = Reticulation::new(None);
+    assert!(env.try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&SplineReticulationAlgorithm::QuadraticBezier));
+
+    let env = crate::environment::Environment::new("APPNAME".into(), vec![("APPNAME_RETICULATION".into(), Ok("new".into()))]);
+    let e = env.try_get(&mut ci).unwrap();
+    assert_eq!(e.to_string(), "Value for environment variable (APPNAME_RETICULATION=\"new\") was not accepted. Expected one of old, quadratic-bezier.");
+    assert_eq!(e.source().unwrap().to_string(), "\"new\" is not one of the choices: old, quadratic-bezier.");
+
+    let schema = crate::schema::item_schema(&ci).to_json_inline();
+    assert_eq!(schema, r#"{"default": "old", "enum": ["old", "quadratic-bezier"], "type": "string"}"#);
+    let entry = crate::docs::Entry::new(&env, &ci);
+    assert_eq!(entry.allowed_values, Some(vec!["old".into(), "quadratic-bezier".into()]));
+  }
+}
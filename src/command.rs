@@ -0,0 +1,366 @@
+//! Source of configuration values printed by a local command, like git credential helpers or the
+//! `password_command` setting of many tools.
+//!
+//! The command is told which item is wanted with `name=<name>` and, for grouped items,
+//! `group=<group>`, either as extra arguments or as lines on its standard input. It should print
+//! the value on standard output and exit successfully; trailing newlines are removed. Anything
+//! it prints on standard error is included in the error when it fails.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::io::Read;
+use std::io::Write;
+use std::process;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::file;
+use crate::schema;
+use crate::ConfigurationItem;
+use crate::ConfigurationValueSource;
+
+/// Commands that take longer than this are killed, unless another timeout is specified.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Output larger than this is not accepted, unless another limit is specified.
+pub const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+// how much of standard error is kept for messages
+const MAX_STDERR_BYTES: usize = 4 * 1024;
+
+/// How the command is told which item is wanted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+  /// As extra arguments, e.g. `helper name=password group=database`.
+  Arguments,
+  /// As lines on standard input, e.g. `name=password\ngroup=database\n`.
+  Stdin,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+  #[error("Could not run command ({command}) for ({name}): {reason}.")]
+  CouldNotRun {
+    name: String,
+    command: String,
+    reason: String,
+  },
+  #[error("Command ({command}) for ({name}) did not finish within {} ms.{}", .timeout.as_millis(), stderr_note(.stderr))]
+  TimedOut {
+    name: String,
+    command: String,
+    timeout: Duration,
+    stderr: String,
+  },
+  #[error("Command ({command}) for ({name}) failed ({status}).{}", stderr_note(.stderr))]
+  Failed {
+    name: String,
+    command: String,
+    status: process::ExitStatus,
+    stderr: String,
+  },
+  #[error("Output of command ({command}) for ({name}) is larger than {max_output_bytes} bytes.")]
+  OutputTooLarge {
+    name: String,
+    command: String,
+    max_output_bytes: usize,
+  },
+  #[error("Output of command ({command}) for ({name}) is not valid unicode.")]
+  NotUnicode {
+    name: String,
+    command: String,
+  },
+  // the value is not shown, as commands are mostly used for secrets
+  #[error("Output of command ({command}) for ({name}) was not accepted. {hint}")]
+  ValueNotAccepted {
+    name: String,
+    command: String,
+    hint: String,
+    source: Box<dyn std::error::Error>,
+  },
+}
+
+fn stderr_note(stderr: &str) -> String {
+  match stderr.trim() {
+    "" => String::new(),
+    s => format!(" stderr: {}", s),
+  }
+}
+
+/// A command run to get values.
+/// Values are cached for the lifetime of the source, so the command runs once per item, except
+/// for items marked `#[volatile]`, which run it every time.
+#[derive(Debug)]
+pub struct Command {
+  program: OsString,
+  args: Vec<OsString>,
+  input: Input,
+  timeout: Duration,
+  max_output_bytes: usize,
+  // output for each item key
+  cache: Mutex<HashMap<String, String>>,
+}
+
+impl Command {
+  pub fn new<S: AsRef<OsStr>>(program: S) -> Self {
+    Self {
+      program: program.as_ref().to_os_string(),
+      args: Vec::new(),
+      input: Input::Arguments,
+      timeout: TIMEOUT,
+      max_output_bytes: MAX_OUTPUT_BYTES,
+      cache: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Pass these arguments before the ones naming the item.
+  pub fn with_args<S: AsRef<OsStr>>(mut self, args: Vec<S>) -> Self {
+    self.args = args.iter().map(|a| a.as_ref().to_os_string()).collect();
+    self
+  }
+
+  /// Choose how the command is told which item is wanted. Defaults to `Input::Arguments`.
+  pub fn with_input(mut self, input: Input) -> Self {
+    self.input = input;
+    self
+  }
+
+  /// Kill the command if it takes longer than this. Defaults to `TIMEOUT`.
+  pub fn with_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Reject output larger than this. Defaults to `MAX_OUTPUT_BYTES`.
+  pub fn with_max_output_bytes(mut self, max_output_bytes: usize) -> Self {
+    self.max_output_bytes = max_output_bytes;
+    self
+  }
+
+  /// Return the command, with its arguments, as it is shown in messages.
+  pub fn command_line(&self) -> String {
+    std::iter::once(&self.program)
+      .chain(self.args.iter())
+      .map(|a| a.to_string_lossy())
+      .collect::<Vec<_>>()
+      .join(" ")
+  }
+
+  // the lines identifying the item, e.g. `name=password` and `group=database`
+  fn request(ci: &dyn ConfigurationItem) -> Vec<String> {
+    let mut request = vec![format!("name={}", schema::property_name(ci))];
+    if let Some(group) = ci.get_group() {
+      request.push(format!("group={}", group));
+    }
+    request
+  }
+
+  // run the command, returning its output without trailing newlines
+  fn run(&self, ci: &dyn ConfigurationItem) -> Result<String, Error> {
+    use self::Error::*;
+    let name = String::from(ci.get_name());
+    let command = self.command_line();
+    let request = Self::request(ci);
+
+    let mut cmd = process::Command::new(&self.program);
+    cmd.args(&self.args)
+      .stdin(if self.input == Input::Stdin { process::Stdio::piped() } else { process::Stdio::null() })
+      .stdout(process::Stdio::piped())
+      .stderr(process::Stdio::piped());
+    if self.input == Input::Arguments {
+      cmd.args(&request);
+    }
+
+    let could_not_run = |e: std::io::Error| CouldNotRun { name: name.clone(), command: command.clone(), reason: e.to_string() };
+    let mut child = cmd.spawn().map_err(could_not_run)?;
+
+    // read the output while waiting, so the command does not block on a full pipe; the readers
+    // report back over channels, so that they can be given up on at the deadline
+    let limit = self.max_output_bytes as u64 + 1;
+    let (stdout_sender, stdout) = mpsc::channel();
+    if let Some(out) = child.stdout.take() {
+      thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stdout_sender.send(out.take(limit).read_to_end(&mut buf).map(|_| buf));
+      });
+    }
+    let stderr = Arc::new(Mutex::new(Vec::new()));
+    let (stderr_sender, stderr_read) = mpsc::channel();
+    if let Some(mut err) = child.stderr.take() {
+      let stderr = Arc::clone(&stderr);
+      thread::spawn(move || {
+        let mut chunk = [0; 512];
+        while let Ok(n) = err.read(&mut chunk) {
+          if n == 0 { break; }
+          let mut stderr = stderr.lock().unwrap();
+          let keep = n.min(MAX_STDERR_BYTES.saturating_sub(stderr.len()));
+          stderr.extend_from_slice(&chunk[..keep]);
+        }
+        let _ = stderr_sender.send(());
+      });
+    }
+    let stderr = || String::from_utf8_lossy(&stderr.lock().unwrap()).into_owned();
+
+    // written from a thread too, so that a command that does not read its input cannot hold us
+    // past the deadline; one that exits without reading it is not an error in itself
+    if let Some(mut stdin) = child.stdin.take() {
+      let mut lines = request.join("\n");
+      lines.push('\n');
+      thread::spawn(move || {
+        let _ = stdin.write_all(lines.as_bytes());
+      });
+    }
+
+    let deadline = Instant::now() + self.timeout;
+    let status = loop {
+      match child.try_wait().map_err(could_not_run)? {
+        Some(status) => break status,
+        None if Instant::now() >= deadline => {
+          let _ = child.kill();
+          let _ = child.wait();
+          return Err(TimedOut { name: name, command: command, timeout: self.timeout, stderr: stderr() });
+        }
+        None => thread::sleep(Duration::from_millis(5)),
+      }
+    };
+
+    // the command may have exited while something it started still holds its output open
+    let output = match stdout.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+      Ok(Ok(buf)) => buf,
+      Ok(Err(e)) => return Err(could_not_run(e)),
+      Err(mpsc::RecvTimeoutError::Timeout) =>
+        return Err(TimedOut { name: name, command: command, timeout: self.timeout, stderr: stderr() }),
+      Err(mpsc::RecvTimeoutError::Disconnected) => Vec::new(),
+    };
+    let _ = stderr_read.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+    // checked first, as a command whose output is cut short may fail writing the rest
+    if output.len() > self.max_output_bytes {
+      return Err(OutputTooLarge { name: name, command: command, max_output_bytes: self.max_output_bytes });
+    }
+    if !status.success() {
+      return Err(Failed { name: name, command: command, status: status, stderr: stderr() });
+    }
+    match String::from_utf8(output) {
+      Ok(s) => Ok(file::trim_newlines(&s).to_string()),
+      Err(_) => Err(NotUnicode { name: name, command: command }),
+    }
+  }
+}
+
+impl ConfigurationValueSource for Command {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    let key = schema::file_key(ci);
+    let volatile = ci.get_metadata().volatile;
+
+    let cached = if volatile { None } else { self.cache.lock().unwrap().get(&key).cloned() };
+    let output = match cached {
+      Some(output) => output,
+      None => match self.run(ci) {
+        Err(e) => return Some(Box::new(e)),
+        Ok(output) => {
+          if !volatile {
+            self.cache.lock().unwrap().insert(key, output.clone());
+          }
+          output
+        }
+      },
+    };
+
+    ci.try_value(&Box::new(output)).map(|e| Box::new(Error::ValueNotAccepted {
+      name: ci.get_name().into(),
+      command: self.command_line(),
+      hint: crate::metadata::hint(ci, e.as_ref()),
+      source: e,
+    }) as Box<dyn std::error::Error>)
+  }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    format!("command {} for {}", self.command_line(), schema::file_key(ci))
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+  use crate::testing::TempDir;
+
+  config!(
+    #[group = "database"]
+    #[secret]
+    (Password String),
+    #[volatile]
+    (Token String),
+    (Port u16)
+  );
+
+  fn sh(script: &str) -> Command {
+    Command::new("sh").with_args(vec!["-c", script, "helper"])
+  }
+
+  #[test]
+  fn item_is_named_by_arguments_or_stdin() {
+    let command = sh("echo \"$@\"");
+    let mut password = Password::new(None);
+    assert!(command.try_get(&mut password).is_none());
+    assert_eq!(password.get(), Some(&String::from("name=password group=database")));
+    assert_eq!(command.describe(&password), "command sh -c echo \"$@\" helper for database.password");
+
+    let command = sh("cat").with_input(Input::Stdin);
+    let mut password = Password::new(None);
+    assert!(command.try_get(&mut password).is_none());
+    assert_eq!(password.get(), Some(&String::from("name=password\ngroup=database")));
+  }
+
+  #[test]
+  fn results_are_cached_unless_volatile() {
+    let dir = TempDir::new("command");
+    let counter = dir.join("runs");
+    let command = sh(&format!("echo run >> {}; echo secret", counter.display()));
+    let runs = || std::fs::read_to_string(&counter).unwrap().lines().count();
+
+    for _ in 0..2 {
+      let mut password = Password::new(None);
+      assert!(command.try_get(&mut password).is_none());
+      assert_eq!(password.get(), Some(&String::from("secret")));
+    }
+    assert_eq!(runs(), 1);
+
+    for _ in 0..2 {
+      let mut token = Token::new(None);
+      assert!(command.try_get(&mut token).is_none());
+    }
+    assert_eq!(runs(), 3);
+  }
+
+  #[test]
+  fn failures_are_explained() {
+    let mut port = Port::new(None);
+    let e = sh("echo 'no such item' >&2; exit 3").try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), "Command (sh -c echo 'no such item' >&2; exit 3 helper) for (Port) failed (exit status: 3). stderr: no such item");
+
+    let e = sh("exec sleep 5").with_timeout(Duration::from_millis(100)).try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), "Command (sh -c exec sleep 5 helper) for (Port) did not finish within 100 ms.");
+
+    // the command exits, but leaves behind a process holding its output open
+    let started = Instant::now();
+    let e = sh("sleep 5 & echo 8080").with_timeout(Duration::from_millis(100)).try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), "Command (sh -c sleep 5 & echo 8080 helper) for (Port) did not finish within 100 ms.");
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    let e = sh("echo 123456789").with_max_output_bytes(4).try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), "Output of command (sh -c echo 123456789 helper) for (Port) is larger than 4 bytes.");
+
+    let e = sh("echo http").try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), "Output of command (sh -c echo http helper) for (Port) was not accepted. Expected a value of type integer.");
+
+    let e = Command::new("/nonexistent/helper").try_get(&mut port).unwrap();
+    assert!(e.to_string().starts_with("Could not run command (/nonexistent/helper) for (Port): "));
+  }
+}
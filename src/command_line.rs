@@ -1,42 +1,393 @@
 use std::env;
+use std::ffi::OsStr;
 use std::ffi::OsString;
 
+use convert_case::Casing;
+use convert_case::Case::Kebab;
+
+use crate::ConfigurationItem;
+use crate::ConfigurationValueSource;
+use crate::dump;
+use crate::value;
+use crate::value::Kind;
+use crate::value::Value;
+
+#[derive(Debug)]
 pub enum Result {
   NoCommandLineArguments,
-  SomeArgumentsWereNotValidUnicode {
-    args: Vec<std::result::Result<String, OsString>>,
-  },
+  /// Arguments are kept as provided, so values that are not valid unicode can still be used by
+  /// `OsString` and `PathBuf` items.
   Success {
-    args: Vec<String>,
+    args: Vec<OsString>,
   }
 }
 
+#[derive(Debug)]
 pub struct CommandLine {
   result: Result,
 }
 
+impl Default for CommandLine {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl CommandLine {
   pub fn new() -> Self {
-    use self::Result::*;
+    Self::from_args_os(env::args_os().collect())
+  }
+
+  /// Use the provided arguments instead of those the process was started with.
+  /// The first argument is the program name, and is ignored.
+  pub fn from_args(args: Vec<String>) -> Self {
+    Self::from_args_os(args.into_iter().map(OsString::from).collect())
+  }
+
+  /// Like `from_args`, for arguments that may not be valid unicode.
+  pub fn from_args_os(args: Vec<OsString>) -> Self {
     Self {
-      result: {
-        let args: Vec<OsString> = env::args_os().collect();
-        if args.len() == 0 { NoCommandLineArguments }
-        else {
-          let results: Vec<std::result::Result<_,_>> = args.into_iter()
-            .map(|arg| arg.into_string())
-            .collect();
+      result:
+        if args.is_empty() { Result::NoCommandLineArguments }
+        else { Result::Success { args: args } }}
+  }
+
+  /// Return the value for the flag, if it was provided.
+  /// `--flag value` and `--flag=value` are both accepted; the last occurrence wins.
+  /// Arguments after `--` are not considered.
+  pub fn lookup(&self, flag: &str) -> std::result::Result<Option<&OsStr>, Error> {
+    Ok(self.lookup_all(flag)?.pop())
+  }
+
+  /// Return every value provided for the flag, in order, e.g. for `--host a --host b`.
+  /// Values before an unset (see `is_unset`) are discarded.
+  pub fn lookup_all(&self, flag: &str) -> std::result::Result<Vec<&OsStr>, Error> {
+    Ok(self.scan(flag, false)?.0)
+  }
+
+  /// Return the value for a boolean flag, if it was provided.
+  /// A bare `--flag` is `true` and `--no-flag` is `false`; `--flag=value` is also accepted, but
+  /// `--flag value` is not, so the flag does not take the next argument.
+  pub fn lookup_switch(&self, flag: &str) -> std::result::Result<Option<&OsStr>, Error> {
+    Ok(self.scan(flag, true)?.0.pop())
+  }
+
+  /// Return `true` if the item was explicitly unset with `--no-flag`, `--unset flag` or
+  /// `--unset=flag`, and no value was provided for it afterwards.
+  /// Boolean flags are only unset by `--unset`, see `lookup_switch`.
+  pub fn is_unset(&self, flag: &str) -> std::result::Result<bool, Error> {
+    Ok(self.scan(flag, false)?.1)
+  }
+
+  fn scan(&self, flag: &str, switch: bool) -> std::result::Result<(Vec<&OsStr>, bool), Error> {
+    let args = match &self.result {
+      Result::NoCommandLineArguments => return Ok((Vec::new(), false)),
+      Result::Success { args } => args,
+    };
+
+    let name = flag.trim_start_matches('-');
+    let negated = format!("--no-{}", name);
+    let inline = format!("{}=", flag);
+    let mut vals = Vec::new();
+    let mut unset = false;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+      if arg == "--" { break; }
+      let unsets =
+        if arg == negated.as_str() { !switch }
+        else if arg == "--unset" { iter.next().map(|v| v == name).unwrap_or(false) }
+        else { arg.to_str().and_then(|a| a.strip_prefix("--unset=")) == Some(name) };
+      if unsets {
+        vals.clear();
+        unset = true;
+      }
+      else if switch && (arg == flag || arg == negated.as_str()) {
+        vals.push(OsStr::new(if arg == flag { "true" } else { "false" }));
+        unset = false;
+      }
+      else if arg == flag {
+        match iter.next() {
+          Some(v) if v != "--" => vals.push(v.as_os_str()),
+          _ => return Err(Error::NoValueForFlag { flag: flag.into() }),
+        }
+        unset = false;
+      }
+      else if let Some(v) = arg.as_encoded_bytes().strip_prefix(inline.as_bytes()) {
+        // SAFETY: the bytes come from an `OsStr`, and are split immediately after an ASCII `=`
+        vals.push(unsafe { OsStr::from_encoded_bytes_unchecked(v) });
+        unset = false;
+      }
+    }
 
-          if results.iter().any(|x| x.is_err()) {
-            SomeArgumentsWereNotValidUnicode { 
-              args: results }}
+    Ok((vals, unset))
+  }
+}
 
-          else {
-            Success {
-              args: results.into_iter().map(|x| x.unwrap()).collect() }}}}}
+/// Return the flag used to provide a value for the item on the command line.
+pub fn flag_name(ci: &dyn ConfigurationItem) -> String {
+  let name = ci.get_name().to_case(Kebab);
+  match ci.get_group() {
+    Some(group) => format!("--{}-{}", group.to_case(Kebab), name),
+    None => format!("--{}", name),
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+  #[error("No value was provided for flag ({flag}).")]
+  NoValueForFlag {
+    flag: String,
+  },
+  #[error("Flag ({flag}) was not provided.")]
+  FlagNotFound {
+    name: String,
+    flag: String,
+  },
+  #[error("Value for flag ({flag}) should be written as key=value.")]
+  MalformedEntry {
+    name: String,
+    flag: String,
+    source: value::ValueError,
+  },
+  #[error("Value for flag ({flag}={value}) was not accepted. {hint}")]
+  ValueNotAccepted {
+    name: String,
+    flag: String,
+    // quoted, with any bytes that are not valid unicode escaped
+    value: String,
+    hint: String,
+    source: Box<dyn std::error::Error>,
+  },
+}
+
+impl ConfigurationValueSource for CommandLine {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    let ci_name = String::from(ci.get_name());
+    let flag = flag_name(ci);
+    let switch = ci.get_kind() == Kind::Boolean;
+
+    match self.scan(&flag, switch).map(|(_, unset)| unset) {
+      Err(e) => return Some(Box::new(e)),
+      Ok(true) => return Some(Box::new(crate::Unset)),
+      Ok(false) => (),
+    }
+
+    use self::Error::*;
+    let kind = ci.get_kind();
+    if kind.is_collection() {
+      // every occurrence of the flag is an element (or an entry, written key=value)
+      let vals = match self.lookup_all(&flag) {
+        Err(e) => return Some(Box::new(e)),
+        Ok(vals) if vals.is_empty() => return Some(Box::new(FlagNotFound { name: ci_name, flag: flag })),
+        Ok(vals) => vals,
+      };
+
+      // elements of lists and maps must be valid unicode
+      let mut strs = Vec::with_capacity(vals.len());
+      for v in vals {
+        match v.to_str() {
+          Some(s) => strs.push(s),
+          None => {
+            let e = <String as value::ConfigurationValue>::from_os_str(v).unwrap_err();
+            return Some(Box::new(ValueNotAccepted {
+              name: ci_name,
+              flag: flag,
+              value: if ci.is_secret() { dump::REDACTED.into() } else { value::quote_os(v) },
+              hint: crate::metadata::hint(ci, e.as_ref()),
+              source: e,
+            }));
+          }
+        }
+      }
+      let vals = strs;
+
+      let value = match kind {
+        Kind::Map(_) => {
+          let mut map = std::collections::BTreeMap::new();
+          for v in vals {
+            match v.find('=') {
+              Some(i) => { map.insert(v[..i].to_string(), Value::String(v[i + 1..].to_string())); }
+              None => return Some(Box::new(MalformedEntry { name: ci_name, flag: flag, source: value::ValueError::MalformedEntry { entry: v.into() } })),
+            }
+          }
+          Value::Map(map)
+        }
+        _ => Value::List(vals.into_iter().map(|v| Value::String(v.into())).collect()),
+      };
+
+      return match ci.try_value(&Box::new(value.clone())) {
+        Some(e) => Some(Box::new(ValueNotAccepted {
+          name: ci_name,
+          flag: flag,
+          value: if ci.is_secret() { dump::REDACTED.into() } else { value.to_json_inline() },
+          hint: crate::metadata::hint(ci, e.as_ref()),
+          source: e,
+        })),
+        None => None,
+      };
+    }
+
+    let found = if switch { self.lookup_switch(&flag) } else { self.lookup(&flag) };
+    match found {
+      Err(e) => Some(Box::new(e)),
+      Ok(None) => Some(Box::new(FlagNotFound {
+        name: ci_name,
+        flag: flag,
+      })),
+      Ok(Some(s)) => match s.to_str() {
+        Some(text) => ci.try_value(&Box::new(text.to_string())),
+        None => ci.try_value(&Box::new(s.to_os_string())),
+      }.map(|e| -> Box<dyn std::error::Error> {
+        Box::new(ValueNotAccepted {
+          name: ci_name,
+          flag: flag,
+          value: if ci.is_secret() { dump::REDACTED.into() } else { value::quote_os(s) },
+          hint: crate::metadata::hint(ci, e.as_ref()),
+          source: e,
+        })
+      }),
+    }
+  }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    format!("command line {}", flag_name(ci))
   }
 }
 
 pub fn new() -> CommandLine {
   CommandLine::new()
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+
+  config!(
+    (CliTestHosts Vec<String>),
+    (CliTestLabels std::collections::BTreeMap<String, String>),
+    (CliTestItem String),
+    #[group = "database"]
+    (CliGroupedItem String)
+  );
+
+  fn args(args: &[&str]) -> CommandLine {
+    CommandLine::from_args(args.iter().map(|a| a.to_string()).collect())
+  }
+
+  #[test]
+  fn flag_names_are_kebab_case() {
+    assert_eq!(flag_name(&CliTestItem::new(None)), "--cli-test-item");
+    assert_eq!(flag_name(&CliGroupedItem::new(None)), "--database-cli-grouped-item");
+  }
+
+  #[test]
+  fn try_get_separate_and_inline_values() {
+    let mut ci = CliTestItem::new(None);
+    assert!(args(&["prog", "--cli-test-item", "a"]).try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&String::from("a")));
+
+    let mut ci = CliTestItem::new(None);
+    assert!(args(&["prog", "--cli-test-item=b"]).try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&String::from("b")));
+  }
+
+  #[test]
+  fn repeated_flags_become_lists_and_maps() {
+    let mut ci = CliTestHosts::new(None);
+    assert!(args(&["prog", "--cli-test-hosts", "a", "--cli-test-hosts=b"]).try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&vec![String::from("a"), String::from("b")]));
+
+    let mut ci = CliTestLabels::new(None);
+    assert!(args(&["prog", "--cli-test-labels", "team=x", "--cli-test-labels=env=prod"]).try_get(&mut ci).is_none());
+    assert_eq!(ci.get().unwrap().get("env"), Some(&String::from("prod")));
+
+    let mut ci = CliTestLabels::new(None);
+    let e = args(&["prog", "--cli-test-labels", "team"]).try_get(&mut ci).unwrap();
+    assert_eq!(e.to_string(), "Value for flag (--cli-test-labels) should be written as key=value.");
+  }
+
+  #[test]
+  fn items_can_be_unset() {
+    for argv in [
+      &["prog", "--cli-test-item", "a", "--no-cli-test-item"][..],
+      &["prog", "--unset", "cli-test-item"],
+      &["prog", "--unset=cli-test-item"],
+    ].iter() {
+      let mut ci = CliTestItem::new(None);
+      assert!(args(argv).try_get(&mut ci).unwrap().is::<crate::Unset>());
+      assert_eq!(ci.get(), None);
+    }
+
+    // a later value wins over an earlier unset
+    let mut ci = CliTestHosts::new(None);
+    assert!(args(&["prog", "--cli-test-hosts", "a", "--no-cli-test-hosts", "--cli-test-hosts=b"]).try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&vec![String::from("b")]));
+  }
+
+  config!(
+    (CliTestPath std::path::PathBuf)
+  );
+
+  #[cfg(unix)]
+  #[test]
+  fn values_that_are_not_unicode() {
+    use std::os::unix::ffi::OsStringExt;
+    let cli = CommandLine::from_args_os(vec![
+      "prog".into(),
+      "--cli-test-item=ok".into(),
+      "--cli-test-path".into(),
+      OsString::from_vec(b"/tmp/caf\xe9".to_vec()),
+      OsString::from_vec(b"--cli-test-hosts=caf\xe9".to_vec()),
+    ]);
+
+    let mut ci = CliTestPath::new(None);
+    assert!(cli.try_get(&mut ci).is_none());
+    assert_eq!(ci.get().unwrap().as_os_str(), OsString::from_vec(b"/tmp/caf\xe9".to_vec()));
+
+    // other arguments are still usable
+    let mut ci = CliTestItem::new(None);
+    assert!(cli.try_get(&mut ci).is_none());
+
+    let mut ci = CliTestHosts::new(None);
+    let e = cli.try_get(&mut ci).unwrap();
+    assert_eq!(e.to_string(), "Value for flag (--cli-test-hosts=\"caf\\xE9\") was not accepted. Expected a value of type list of string.");
+    assert_eq!(e.source().unwrap().to_string(), "Expected text, but \"caf\\xE9\" is not valid unicode (invalid bytes: \\xE9).");
+  }
+
+  config!(
+    (CliTestVerbose bool)
+  );
+
+  #[test]
+  fn boolean_flags_are_switches() {
+    let mut ci = CliTestVerbose::new(None);
+    let cli = args(&["prog", "--cli-test-verbose", "--cli-test-item", "a"]);
+    assert!(cli.try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&true));
+    let mut item = CliTestItem::new(None);
+    assert!(cli.try_get(&mut item).is_none());
+    assert_eq!(item.get(), Some(&String::from("a")));
+
+    for (argv, expected) in [
+      (&["prog", "--cli-test-verbose", "--no-cli-test-verbose"][..], false),
+      (&["prog", "--no-cli-test-verbose", "--cli-test-verbose"], true),
+      (&["prog", "--cli-test-verbose=false"], false),
+    ].iter() {
+      let mut ci = CliTestVerbose::new(None);
+      assert!(args(argv).try_get(&mut ci).is_none());
+      assert_eq!(ci.get(), Some(expected));
+    }
+
+    let mut ci = CliTestVerbose::new(None);
+    assert!(args(&["prog", "--cli-test-verbose", "--unset=cli-test-verbose"]).try_get(&mut ci).unwrap().is::<crate::Unset>());
+  }
+
+  #[test]
+  fn arguments_after_double_dash_are_ignored() {
+    let mut ci = CliTestItem::new(None);
+    assert!(args(&["prog", "--", "--cli-test-item", "a"]).try_get(&mut ci).is_some());
+    assert_eq!(ci.get(), None);
+  }
+}
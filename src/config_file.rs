@@ -0,0 +1,517 @@
+//! Source of configuration values read from a TOML, JSON or YAML file.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::ConfigurationItem;
+use crate::ConfigurationValueSource;
+use crate::dump;
+use crate::file;
+use crate::interpolate;
+use crate::path;
+use crate::schema;
+use crate::value::Value;
+
+/// Configuration files larger than this are not loaded, unless another limit is specified.
+pub const MAX_FILE_SIZE_BYTES: u64 = 1024 * 1024;
+
+/// Formats configuration files can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Toml,
+  Json,
+  Yaml,
+}
+
+impl Format {
+  /// Guess the format from the file extension.
+  pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+    match path.as_ref().extension().and_then(|e| e.to_str()) {
+      Some("toml") => Some(Format::Toml),
+      Some("json") => Some(Format::Json),
+      Some("yaml") | Some("yml") => Some(Format::Yaml),
+      _ => None,
+    }
+  }
+
+  /// Parse the contents of a file in this format into a table of values.
+  /// Keys set to `null` are left out; `null` list elements are errors.
+  pub fn parse(&self, contents: &str) -> Result<BTreeMap<String, Value>, String> {
+    Ok(self.parse_with_nulls(contents)?.0)
+  }
+
+  // also return the dotted keys that were set to `null`, e.g. `database.password`
+  fn parse_with_nulls(&self, contents: &str) -> Result<(BTreeMap<String, Value>, BTreeSet<String>), String> {
+    let mut nulls = BTreeSet::new();
+    let value = match self {
+      Format::Toml => from_toml(contents.parse::<toml::Value>().map_err(|e| e.to_string())?),
+      Format::Json => from_json(serde_json::from_str(contents).map_err(|e| e.to_string())?, "", &mut nulls)?,
+      Format::Yaml => from_yaml(serde_yaml::from_str(contents).map_err(|e| e.to_string())?, "", &mut nulls)?,
+    };
+
+    match value {
+      Some(Value::Map(table)) => Ok((table, nulls)),
+      // an empty YAML document
+      None => Ok((BTreeMap::new(), nulls)),
+      Some(other) => Err(format!("expected a table at the top level, found a {}", other.kind_name())),
+    }
+  }
+}
+
+fn child(path: &str, key: &str) -> String {
+  if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) }
+}
+
+fn from_toml(value: toml::Value) -> Option<Value> {
+  use toml::Value as T;
+  Some(match value {
+    T::String(s) => Value::String(s),
+    T::Integer(i) => Value::Integer(i.into()),
+    T::Float(f) => Value::Number(f),
+    T::Boolean(b) => Value::Boolean(b),
+    T::Datetime(d) => Value::String(d.to_string()),
+    T::Array(items) => Value::List(items.into_iter().filter_map(from_toml).collect()),
+    T::Table(table) => Value::Map(table.into_iter().filter_map(|(k, v)| from_toml(v).map(|v| (k, v))).collect()),
+  })
+}
+
+// a list element, e.g. `hosts[1]`
+fn element(path: &str, index: usize) -> String {
+  format!("{}[{}]", path, index)
+}
+
+// lists cannot leave an element out, so a null element is an error rather than an unset value
+fn list<T, F>(items: Vec<T>, path: &str, convert: F) -> Result<Value, String>
+where F: Fn(T, &str, &mut BTreeSet<String>) -> Result<Option<Value>, String> {
+  let mut list = Vec::with_capacity(items.len());
+  for (i, v) in items.into_iter().enumerate() {
+    let path = element(path, i);
+    match convert(v, &path, &mut BTreeSet::new())? {
+      Some(v) => list.push(v),
+      None => return Err(format!("{} is null, but lists cannot contain null", path)),
+    }
+  }
+  Ok(Value::List(list))
+}
+
+fn from_json(value: serde_json::Value, path: &str, nulls: &mut BTreeSet<String>) -> Result<Option<Value>, String> {
+  use serde_json::Value as J;
+  Ok(match value {
+    J::Null => None,
+    J::Bool(b) => Some(Value::Boolean(b)),
+    J::Number(n) => Some(match (n.as_i64(), n.as_u64()) {
+      (Some(i), _) => Value::Integer(i.into()),
+      (None, Some(u)) => Value::Integer(u.into()),
+      _ => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+    }),
+    J::String(s) => Some(Value::String(s)),
+    J::Array(items) => Some(list(items, path, from_json)?),
+    J::Object(table) => {
+      let mut map = BTreeMap::new();
+      for (k, v) in table {
+        let key = child(path, &k);
+        match from_json(v, &key, nulls)? {
+          Some(v) => { map.insert(k, v); }
+          None => { nulls.insert(key); }
+        }
+      }
+      Some(Value::Map(map))
+    }
+  })
+}
+
+fn from_yaml(value: serde_yaml::Value, path: &str, nulls: &mut BTreeSet<String>) -> Result<Option<Value>, String> {
+  use serde_yaml::Value as Y;
+  Ok(match value {
+    Y::Null => None,
+    Y::Bool(b) => Some(Value::Boolean(b)),
+    Y::Number(n) => Some(match (n.as_i64(), n.as_u64()) {
+      (Some(i), _) => Value::Integer(i.into()),
+      (None, Some(u)) => Value::Integer(u.into()),
+      _ => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+    }),
+    Y::String(s) => Some(Value::String(s)),
+    Y::Sequence(items) => Some(list(items, path, from_yaml)?),
+    Y::Mapping(table) => {
+      let mut map = BTreeMap::new();
+      for (k, v) in table {
+        let k = match from_yaml(k, path, &mut BTreeSet::new())?.and_then(|k| k.as_scalar_string()) {
+          Some(k) => k,
+          None => return Err(format!(
+            "{} has a key that is not a string, number or boolean",
+            if path.is_empty() { "the top level" } else { path })),
+        };
+        let key = child(path, &k);
+        match from_yaml(v, &key, nulls)? {
+          Some(v) => { map.insert(k, v); }
+          None => { nulls.insert(key); }
+        }
+      }
+      Some(Value::Map(map))
+    }
+  })
+}
+
+/// Describes why a configuration file could not be loaded.
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum LoadError {
+  #[error("Could not read configuration file ({path}): {reason}.")]
+  CouldNotRead {
+    path: PathBuf,
+    reason: String,
+  },
+  #[error("Configuration file ({path}) is not valid unicode.")]
+  NotUnicode {
+    path: PathBuf,
+  },
+  #[error("Could not parse configuration file ({path}): {reason}")]
+  CouldNotParse {
+    path: PathBuf,
+    reason: String,
+  },
+  #[error("Unknown format for configuration file ({path}); expected .toml, .json, .yaml or .yml.")]
+  UnknownFormat {
+    path: PathBuf,
+  },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+  #[error("Key ({key}) not found in configuration file ({path}).")]
+  KeyNotFound {
+    name: String,
+    key: String,
+    path: PathBuf,
+  },
+  #[error("Value for key ({key}={}) in configuration file ({path}) was not accepted. {hint}", .value.to_json_inline())]
+  ValueNotAccepted {
+    name: String,
+    key: String,
+    path: PathBuf,
+    value: Value,
+    hint: String,
+    source: Box<dyn std::error::Error>,
+  },
+}
+
+/// A configuration file, loaded when the source is created.
+#[derive(Debug)]
+pub struct ConfigurationFile {
+  path: PathBuf,
+  table: Result<BTreeMap<String, Value>, LoadError>,
+  // keys explicitly set to `null`
+  nulls: BTreeSet<String>,
+  // for a section of a file, the keys leading to it, e.g. `profile.prod.`
+  prefix: String,
+}
+
+impl ConfigurationFile {
+  /// Load the file, guessing the format from its extension.
+  pub fn new<P: AsRef<Path>>(path: P) -> Self {
+    match Format::from_path(&path) {
+      Some(format) => Self::with_format(path, format, MAX_FILE_SIZE_BYTES),
+      None => Self {
+        path: path.as_ref().to_path_buf(),
+        table: Err(LoadError::UnknownFormat { path: path.as_ref().to_path_buf() }),
+        nulls: BTreeSet::new(),
+        prefix: String::new(),
+      },
+    }
+  }
+
+  /// Load the file in the specified format.
+  /// Files larger than `max_file_size_bytes` are not loaded.
+  pub fn with_format<P: AsRef<Path>>(path: P, format: Format, max_file_size_bytes: u64) -> Self {
+    let path = path.as_ref().to_path_buf();
+    let f = file::File::new(&path, max_file_size_bytes);
+    let table = match f.contents() {
+      None => Err(LoadError::CouldNotRead { path: path.clone(), reason: f.result().to_string() }),
+      Some(bytes) => match std::str::from_utf8(bytes) {
+        Err(_) => Err(LoadError::NotUnicode { path: path.clone() }),
+        Ok(contents) => format.parse_with_nulls(contents)
+          .map_err(|reason| LoadError::CouldNotParse { path: path.clone(), reason: reason }),
+      },
+    };
+
+    Self::from_parsed(path, table)
+  }
+
+  /// Use contents that have already been read. `path` is used in messages.
+  pub fn from_str<P: AsRef<Path>>(path: P, contents: &str, format: Format) -> Self {
+    let path = path.as_ref().to_path_buf();
+    let table = format.parse_with_nulls(contents)
+      .map_err(|reason| LoadError::CouldNotParse { path: path.clone(), reason: reason });
+    Self::from_parsed(path, table)
+  }
+
+  fn from_parsed(path: PathBuf, parsed: Result<(BTreeMap<String, Value>, BTreeSet<String>), LoadError>) -> Self {
+    match parsed {
+      Ok((table, nulls)) => Self { path: path, table: Ok(table), nulls: nulls, prefix: String::new() },
+      Err(e) => Self { path: path, table: Err(e), nulls: BTreeSet::new(), prefix: String::new() },
+    }
+  }
+
+  /// Return a source for the table at `key` in this file, e.g. `profile.prod`, where item keys are
+  /// relative to that table. The table may be missing, in which case no items are found.
+  pub fn section(&self, key: &str) -> Self {
+    let prefix = format!("{}.", key);
+    let table = self.table.clone().map(|table| {
+      let mut table = Value::Map(table);
+      for part in key.split('.') {
+        table = match table {
+          Value::Map(mut t) => t.remove(part).unwrap_or_else(|| Value::Map(BTreeMap::new())),
+          _ => Value::Map(BTreeMap::new()),
+        };
+      }
+      match table {
+        Value::Map(t) => t,
+        _ => BTreeMap::new(),
+      }
+    });
+
+    Self {
+      path: self.path.clone(),
+      table: table,
+      nulls: self.nulls.iter().filter_map(|k| k.strip_prefix(&prefix)).map(String::from).collect(),
+      prefix: format!("{}{}", self.prefix, prefix),
+    }
+  }
+
+  /// Return why the file could not be loaded, if it could not.
+  pub fn load_error(&self) -> Option<&LoadError> {
+    self.table.as_ref().err()
+  }
+
+  /// Describe the file (or the section of it) this source reads, e.g. "file config.toml".
+  pub fn describe_file(&self) -> String {
+    match self.prefix.strip_suffix('.') {
+      Some(section) => format!("file {} section {}", self.path.display(), section),
+      None => format!("file {}", self.path.display()),
+    }
+  }
+
+  // the full key for the item, including the section
+  fn key(&self, ci: &dyn ConfigurationItem) -> String {
+    format!("{}{}", self.prefix, schema::file_key(ci))
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  // relative paths are relative to the directory containing the file
+  fn base(&self) -> &Path {
+    self.path.parent().unwrap_or_else(|| Path::new(""))
+  }
+
+  // pass the value to the item; values with references are resolved after they are expanded, by
+  // items that expand them
+  fn provide(&self, ci: &mut dyn ConfigurationItem, value: &Value) -> Option<Box<dyn std::error::Error>> {
+    if interpolate::has_references(value) {
+      let unresolved = path::Unresolved { value: value.clone(), base: self.base().to_path_buf() };
+      match ci.try_value(&Box::new(unresolved)) {
+        Some(e) if e.is::<crate::ValueNotHandled>() => (),
+        result => return result,
+      }
+    }
+    match path::resolve(value, &ci.get_kind(), self.base()) {
+      Ok(resolved) => ci.try_value(&Box::new(resolved)),
+      Err(e) => Some(Box::new(e)),
+    }
+  }
+
+  /// Return the value for the item, if the file was loaded and contains one.
+  pub fn lookup(&self, ci: &dyn ConfigurationItem) -> Result<Option<&Value>, LoadError> {
+    let table = self.table.as_ref().map_err(|e| e.clone())?;
+    let table = match ci.get_group() {
+      None => table,
+      Some(group) => match table.get(group) {
+        Some(Value::Map(t)) => t,
+        _ => return Ok(None),
+      },
+    };
+
+    Ok(table.get(&schema::property_name(ci)))
+  }
+
+  /// Return `true` if the file sets a value for the item, or explicitly unsets it with `null`.
+  pub fn contains(&self, ci: &dyn ConfigurationItem) -> bool {
+    self.nulls.contains(&schema::file_key(ci)) || matches!(self.lookup(ci), Ok(Some(_)))
+  }
+
+  /// Return the value at the top level of the file with this key, if the file was loaded and
+  /// contains one.
+  pub fn get(&self, key: &str) -> Option<&Value> {
+    self.table.as_ref().ok().and_then(|t| t.get(key))
+  }
+}
+
+impl ConfigurationValueSource for ConfigurationFile {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    let ci_name = String::from(ci.get_name());
+    let key = self.key(ci);
+
+    if self.nulls.contains(&schema::file_key(ci)) {
+      return Some(Box::new(crate::Unset));
+    }
+
+    use self::Error::*;
+    match self.lookup(ci) {
+      Err(e) => Some(Box::new(e)),
+      Ok(None) => Some(Box::new(KeyNotFound {
+        name: ci_name,
+        key: key,
+        path: self.path.clone(),
+      })),
+      Ok(Some(value)) => match self.provide(ci, value) {
+        Some(e) => Some(Box::new(ValueNotAccepted {
+          name: ci_name,
+          key: key,
+          path: self.path.clone(),
+          value: if ci.is_secret() { Value::String(dump::REDACTED.into()) } else { value.clone() },
+          hint: crate::metadata::hint(ci, e.as_ref()),
+          source: e,
+        })),
+        None => None,
+      },
+    }
+  }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    format!("file {} key {}", self.path.display(), self.key(ci))
+  }
+}
+
+pub fn new<P: AsRef<Path>>(path: P) -> ConfigurationFile {
+  ConfigurationFile::new(path)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use std::collections::HashMap;
+  use crate::config;
+  use crate::testing::TempDir;
+
+  config!(
+    (MyThreshold f64),
+    (Hosts Vec<String>),
+    (Ports Vec<u16>),
+    #[group = "database"]
+    (Labels HashMap<String, String>)
+  );
+
+  const TOML: &str = r#"
+my_threshold = 4
+hosts = ["a", "b"]
+ports = [80, "http"]
+
+[database]
+labels = { team = "x" }
+"#;
+
+  #[test]
+  fn native_arrays_and_tables() {
+    let f = ConfigurationFile::from_str("config.toml", TOML, Format::Toml);
+
+    let mut threshold = MyThreshold::new(None);
+    assert!(f.try_get(&mut threshold).is_none());
+    assert_eq!(threshold.get(), Some(&4.0));
+
+    let mut hosts = Hosts::new(None);
+    assert!(f.try_get(&mut hosts).is_none());
+    assert_eq!(hosts.get(), Some(&vec![String::from("a"), String::from("b")]));
+
+    let mut labels = Labels::new(None);
+    assert!(f.try_get(&mut labels).is_none());
+    assert_eq!(labels.get().unwrap().get("team"), Some(&String::from("x")));
+    assert_eq!(f.describe(&labels), "file config.toml key database.labels");
+  }
+
+  #[test]
+  fn element_errors_name_the_index() {
+    let f = ConfigurationFile::from_str("config.toml", TOML, Format::Toml);
+    let mut ports = Ports::new(None);
+    let e = f.try_get(&mut ports).unwrap();
+    assert_eq!(e.source().unwrap().to_string(), "Element 1 was not accepted: invalid digit found in string");
+  }
+
+  #[test]
+  fn json_and_yaml() {
+    let f = ConfigurationFile::from_str("config.json", r#"{"hosts": ["a"], "database": {"labels": {"team": "x"}}}"#, Format::Json);
+    let mut hosts = Hosts::new(None);
+    assert!(f.try_get(&mut hosts).is_none());
+    assert_eq!(hosts.get(), Some(&vec![String::from("a")]));
+
+    let f = ConfigurationFile::from_str("config.yaml", "hosts:\n  - a\n  - b\nmy_threshold: 1.5\n", Format::Yaml);
+    let mut hosts = Hosts::new(None);
+    assert!(f.try_get(&mut hosts).is_none());
+    assert_eq!(hosts.get().map(|h| h.len()), Some(2));
+  }
+
+  #[test]
+  fn null_unsets_the_item() {
+    let f = ConfigurationFile::from_str("config.yaml", "my_threshold: ~\ndatabase:\n  labels: null\n", Format::Yaml);
+    let mut threshold = MyThreshold::new(None);
+    assert!(f.try_get(&mut threshold).unwrap().is::<crate::Unset>());
+    let mut labels = Labels::new(None);
+    assert!(f.try_get(&mut labels).unwrap().is::<crate::Unset>());
+
+    let f = ConfigurationFile::from_str("config.json", r#"{"hosts": null}"#, Format::Json);
+    let mut hosts = Hosts::new(None);
+    assert!(f.try_get(&mut hosts).unwrap().is::<crate::Unset>());
+    assert_eq!(hosts.get(), None);
+  }
+
+  #[test]
+  fn null_elements_and_complex_keys_are_rejected() {
+    let e = Format::Json.parse(r#"{"hosts": ["a", null, "c"]}"#).unwrap_err();
+    assert_eq!(e, "hosts[1] is null, but lists cannot contain null");
+    let e = Format::Yaml.parse("database:
+  labels:
+    - a
+    - ~
+").unwrap_err();
+    assert_eq!(e, "database.labels[1] is null, but lists cannot contain null");
+    let e = Format::Yaml.parse("database:
+  [a, b]: 1
+").unwrap_err();
+    assert_eq!(e, "database has a key that is not a string, number or boolean");
+  }
+
+  config!(
+    (DataDir std::path::PathBuf),
+    #[is_file]
+    (CertFile std::path::PathBuf)
+  );
+
+  #[test]
+  fn paths_are_relative_to_the_file() {
+    let dir = TempDir::new("config-file-paths");
+    std::fs::create_dir_all(dir.join("certs")).unwrap();
+    std::fs::write(dir.join("certs/server.pem"), "").unwrap();
+    let f = ConfigurationFile::from_str(dir.join("config.toml"), "data_dir = \"cache\"\ncert_file = \"certs/server.pem\"\n", Format::Toml);
+
+    let mut data = DataDir::new(None);
+    assert!(f.try_get(&mut data).is_none());
+    assert_eq!(data.get(), Some(&dir.join("cache")));
+
+    let mut cert = CertFile::new(None);
+    assert!(f.try_get(&mut cert).is_none());
+
+    let f = ConfigurationFile::from_str(dir.join("config.toml"), "cert_file = \"certs\"\n", Format::Toml);
+    let e = f.try_get(&mut cert).unwrap();
+    assert!(crate::constraint::find_invalid(e.as_ref()).unwrap().to_string().ends_with("is invalid: it must be a file."));
+  }
+
+  #[test]
+  fn missing_file() {
+    let f = ConfigurationFile::new("/nonexistent/config.toml");
+    let mut hosts = Hosts::new(None);
+    let e = f.try_get(&mut hosts).unwrap();
+    assert!(e.to_string().starts_with("Could not read configuration file (/nonexistent/config.toml): could not open file"));
+  }
+}
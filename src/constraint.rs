@@ -0,0 +1,259 @@
+//! Declarative checks applied to values after they have been parsed.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+use crate::dump;
+use crate::human;
+use crate::path;
+use crate::value::ConfigurationValue;
+use crate::value::Kind;
+use crate::value::Value;
+
+/// A rule that the value of a configuration item must satisfy.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+  /// The value must be at least this large.
+  Minimum(Value),
+  /// The value must be at most this large.
+  Maximum(Value),
+  /// Strings must have at least this many characters, lists at least this many elements.
+  MinLength(usize),
+  /// Strings must have at most this many characters, lists at most this many elements.
+  MaxLength(usize),
+  /// Strings must match this regular expression.
+  Pattern(&'static str),
+  /// The value must be one of these.
+  OneOf(Vec<Value>),
+  /// Strings and lists must not be empty.
+  NonEmpty,
+  /// Paths (or every path in a list or map) must pass this check.
+  Path(path::Check),
+}
+
+/// Compare two values of an item of this kind.
+/// Durations, byte sizes and percentages are compared by what they measure, not as text.
+pub fn compare(a: &Value, b: &Value, kind: &Kind) -> Option<Ordering> {
+  match (kind, a, b) {
+    (Kind::Duration, Value::String(a), Value::String(b)) =>
+      human::parse_duration(a).ok()?.partial_cmp(&human::parse_duration(b).ok()?),
+    (Kind::ByteSize, Value::String(a), Value::String(b)) =>
+      human::parse_byte_size(a).ok()?.partial_cmp(&human::parse_byte_size(b).ok()?),
+    (Kind::Percentage, Value::String(a), Value::String(b)) =>
+      human::parse_percentage(a).ok()?.partial_cmp(&human::parse_percentage(b).ok()?),
+    (_, Value::Integer(a), Value::Integer(b)) => a.partial_cmp(b),
+    (_, Value::Integer(a), Value::Number(b)) => (*a as f64).partial_cmp(b),
+    (_, Value::Number(a), Value::Integer(b)) => a.partial_cmp(&(*b as f64)),
+    (_, Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+    (_, Value::String(a), Value::String(b)) => a.partial_cmp(b),
+    _ => None,
+  }
+}
+
+/// Compile the pattern, once however often it is checked.
+fn regex(pattern: &'static str) -> Result<regex::Regex, regex::Error> {
+  static COMPILED: OnceLock<Mutex<HashMap<&'static str, Result<regex::Regex, regex::Error>>>> = OnceLock::new();
+  let mut compiled = COMPILED.get_or_init(Default::default).lock().unwrap();
+  compiled.entry(pattern).or_insert_with(|| regex::Regex::new(pattern)).clone()
+}
+
+fn length(value: &Value) -> Option<usize> {
+  match value {
+    Value::String(s) => Some(s.chars().count()),
+    Value::List(l) => Some(l.len()),
+    Value::Map(m) => Some(m.len()),
+    _ => None,
+  }
+}
+
+impl Constraint {
+  /// Describe the constraint, e.g. "must be at least 1".
+  pub fn describe(&self) -> String {
+    use self::Constraint::*;
+    match self {
+      Minimum(min) => format!("must be at least {}", min.to_json_inline()),
+      Maximum(max) => format!("must be at most {}", max.to_json_inline()),
+      MinLength(min) => format!("must have a length of at least {}", min),
+      MaxLength(max) => format!("must have a length of at most {}", max),
+      Pattern(pattern) => format!("must match the pattern {:?}", pattern),
+      OneOf(values) => format!("must be one of {}", values.iter().map(|v| v.to_json_inline()).collect::<Vec<_>>().join(", ")),
+      NonEmpty => String::from("must not be empty"),
+      Path(check) => check.describe().to_string(),
+    }
+  }
+
+  /// Return `true` if the value, of an item of this kind, satisfies this constraint.
+  pub fn is_satisfied_by(&self, value: &Value, kind: &Kind) -> bool {
+    use self::Constraint::*;
+    match self {
+      Minimum(min) => matches!(compare(value, min, kind), Some(Ordering::Greater) | Some(Ordering::Equal)),
+      Maximum(max) => matches!(compare(value, max, kind), Some(Ordering::Less) | Some(Ordering::Equal)),
+      MinLength(min) => length(value).is_some_and(|len| len >= *min),
+      MaxLength(max) => length(value).is_some_and(|len| len <= *max),
+      Pattern(pattern) => match (value, regex(pattern)) {
+        (Value::String(s), Ok(re)) => re.is_match(s),
+        _ => false,
+      },
+      OneOf(values) => values.contains(value),
+      NonEmpty => length(value).is_some_and(|len| len > 0),
+      Path(check) => match value {
+        Value::String(s) => check.is_satisfied_by(std::path::Path::new(s)),
+        Value::List(items) => items.iter().all(|v| self.is_satisfied_by(v, kind)),
+        Value::Map(map) => map.values().all(|v| self.is_satisfied_by(v, kind)),
+        _ => false,
+      },
+    }
+  }
+
+  /// Check the value against this constraint.
+  /// Returns a description of the problem if the value does not satisfy it.
+  pub fn check(&self, value: &Value, kind: &Kind) -> Option<String> {
+    if self.is_satisfied_by(value, kind) { None }
+    else { Some(self.describe()) }
+  }
+}
+
+/// Returned when a value was parsed successfully, but did not satisfy the item's constraints.
+#[derive(thiserror::Error, Debug)]
+#[error("Value {} is invalid: it {}.", .value.as_ref().map_or(dump::REDACTED.into(), Value::to_json_inline), .violations.join(", and it "))]
+pub struct Invalid {
+  /// The value, or `None` if it must not be displayed.
+  pub value: Option<Value>,
+  pub violations: Vec<String>,
+}
+
+impl Invalid {
+  /// Return the same error without the value, e.g. for a value that was decrypted.
+  pub fn redacted(&self) -> Self {
+    Self { value: None, violations: self.violations.clone() }
+  }
+}
+
+/// Check the value, of an item of this kind, against all of the constraints.
+pub fn check(value: &Value, kind: &Kind, constraints: &[Constraint]) -> Result<(), Invalid> {
+  let violations: Vec<String> = constraints.iter().filter_map(|c| c.check(value, kind)).collect();
+  if violations.is_empty() { Ok(()) }
+  else { Err(Invalid { value: Some(value.clone()), violations: violations }) }
+}
+
+/// Check an item's value against all of the constraints.
+/// Unlike `check`, paths are checked as they are, so those that are not valid unicode can pass.
+pub fn check_value<T: ConfigurationValue>(value: &T, constraints: &[Constraint]) -> Result<(), Invalid> {
+  let (converted, kind) = (value.to_value(), T::kind());
+  let violations: Vec<String> = constraints.iter()
+    .filter_map(|c| match c {
+      Constraint::Path(check) if value.paths().iter().all(|p| check.is_satisfied_by(p)) => None,
+      Constraint::Path(_) => Some(c.describe()),
+      _ => c.check(&converted, &kind),
+    })
+    .collect();
+  if violations.is_empty() { Ok(()) }
+  else { Err(Invalid { value: Some(converted), violations: violations }) }
+}
+
+/// Returned when an item's `#[pattern]` is not a valid regular expression.
+#[derive(thiserror::Error, Debug)]
+#[error("Pattern {pattern:?} is not a valid regular expression: {source}")]
+pub struct InvalidPattern {
+  pub pattern: &'static str,
+  pub source: regex::Error,
+}
+
+/// Check that the constraints themselves are usable, i.e. that every pattern compiles.
+pub fn check_patterns(constraints: &[Constraint]) -> Result<(), InvalidPattern> {
+  for constraint in constraints {
+    if let Constraint::Pattern(pattern) = constraint {
+      regex(pattern).map_err(|e| InvalidPattern { pattern: pattern, source: e })?;
+    }
+  }
+  Ok(())
+}
+
+/// Stands in for an error that could show a secret value.
+#[derive(thiserror::Error, Debug)]
+#[error("The value was not accepted; the reason is not shown because the value is secret.")]
+pub struct Redacted;
+
+/// Return an error saying why a value was not accepted that does not show the value: the `Invalid`
+/// error that caused `e` without its value, or `Redacted`.
+pub fn redact(e: &(dyn Error + 'static)) -> Box<dyn Error> {
+  match find_invalid(e) {
+    Some(invalid) => Box::new(invalid.redacted()),
+    None => Box::new(Redacted),
+  }
+}
+
+/// Return the error, or `redact(e)` if it is about the value of a secret item.
+pub fn redact_if(secret: bool, e: Box<dyn Error>) -> Box<dyn Error> {
+  if secret { redact(e.as_ref()) } else { e }
+}
+
+/// Find the `Invalid` error that caused this error, if any.
+pub fn find_invalid<'e>(e: &'e (dyn Error + 'static)) -> Option<&'e Invalid> {
+  let mut current = Some(e);
+  while let Some(e) = current {
+    if let Some(invalid) = e.downcast_ref::<Invalid>() {
+      return Some(invalid);
+    }
+    current = e.source();
+  }
+  None
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn ranges_compare_integers_and_numbers() {
+    let constraints = [Constraint::Minimum(Value::Integer(1)), Constraint::Maximum(Value::Number(10.0))];
+    assert!(check(&Value::Integer(5), &Kind::Integer, &constraints).is_ok());
+    assert!(check(&Value::Number(10.5), &Kind::Number, &constraints).is_err());
+
+    let e = check(&Value::Integer(0), &Kind::Integer, &constraints).unwrap_err();
+    assert_eq!(e.to_string(), "Value 0 is invalid: it must be at least 1.");
+  }
+
+  #[test]
+  fn strings() {
+    let constraints = [Constraint::NonEmpty, Constraint::MaxLength(3), Constraint::Pattern("^[a-z]*$")];
+    assert!(check(&Value::String("abc".into()), &Kind::String, &constraints).is_ok());
+
+    let e = check(&Value::String("ABCD".into()), &Kind::String, &constraints).unwrap_err();
+    assert_eq!(e.to_string(), "Value \"ABCD\" is invalid: it must have a length of at most 3, and it must match the pattern \"^[a-z]*$\".");
+
+    let e = check(&Value::String("".into()), &Kind::String, &constraints).unwrap_err();
+    assert_eq!(e.violations, vec![String::from("must not be empty")]);
+  }
+
+  #[test]
+  fn human_values_compare_by_magnitude() {
+    let constraints = [Constraint::Maximum(Value::String("1h".into()))];
+    assert!(check(&Value::String("30s".into()), &Kind::Duration, &constraints).is_ok());
+    assert!(check(&Value::String("2h".into()), &Kind::Duration, &constraints).is_err());
+
+    let constraints = [Constraint::Minimum(Value::String("512KiB".into()))];
+    assert!(check(&Value::String("1MiB".into()), &Kind::ByteSize, &constraints).is_ok());
+    assert!(check(&Value::String("9%".into()), &Kind::Percentage, &[Constraint::Maximum(Value::String("10%".into()))]).is_ok());
+  }
+
+  #[test]
+  fn invalid_patterns_are_reported() {
+    let constraints = [Constraint::Pattern("^[a-z")];
+    let e = check_patterns(&constraints).unwrap_err();
+    assert!(e.to_string().starts_with("Pattern \"^[a-z\" is not a valid regular expression: "));
+    assert!(check_patterns(&[Constraint::Pattern("^[a-z]*$")]).is_ok());
+  }
+
+  #[test]
+  fn one_of() {
+    let constraints = [Constraint::OneOf(vec![Value::String("old".into()), Value::String("new".into())])];
+    assert!(check(&Value::String("old".into()), &Kind::String, &constraints).is_ok());
+    assert_eq!(
+      check(&Value::String("other".into()), &Kind::String, &constraints).unwrap_err().violations,
+      vec![String::from("must be one of \"old\", \"new\"")]);
+  }
+}
@@ -0,0 +1,120 @@
+//! Source of configuration values read from systemd credentials.
+//!
+//! systemd passes credentials to a service, from `LoadCredential=`, `LoadCredentialEncrypted=` and
+//! `SetCredential=`, as files in a private directory named by `$CREDENTIALS_DIRECTORY`. Unlike
+//! environment variables, they are not inherited by child processes or visible in `/proc`.
+
+use std::env;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::directory::Directory;
+use crate::directory::Naming;
+use crate::ConfigurationItem;
+use crate::ConfigurationValueSource;
+
+/// The variable systemd sets to the directory holding the service's credentials.
+pub const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+  #[error("Credential for ({name}) not available: {CREDENTIALS_DIRECTORY} is not set; is the service started by systemd with LoadCredential= or SetCredential=?")]
+  DirectoryNotSet {
+    name: String,
+  },
+}
+
+/// The credentials passed to the service, one file per item.
+/// Credentials are named by `Naming::FileKey` unless another mapping is chosen, so the item
+/// `password` in the group `database` is read from the credential `database.password`, as in
+/// `LoadCredential=database.password:/etc/myapp/db-password`.
+#[derive(Debug)]
+pub struct Credentials {
+  directory: Option<Directory>,
+}
+
+impl Credentials {
+  /// Read credentials from the directory named by `$CREDENTIALS_DIRECTORY`, if it is set.
+  pub fn from_env() -> Self {
+    match env::var_os(CREDENTIALS_DIRECTORY) {
+      Some(dir) if !dir.is_empty() => Self::new(dir),
+      _ => Self { directory: None },
+    }
+  }
+
+  /// Read credentials from `dir`, e.g. a temporary directory in tests.
+  pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+    Self { directory: Some(Directory::new(dir)) }
+  }
+
+  /// Choose how the credential for an item is named. Defaults to `Naming::FileKey`.
+  pub fn with_naming(mut self, naming: Naming) -> Self {
+    self.directory = self.directory.map(|d| d.with_naming(naming));
+    self
+  }
+
+  /// Credentials larger than this are not read. Defaults to `directory::MAX_FILE_SIZE_BYTES`.
+  pub fn with_max_file_size_bytes(mut self, max_file_size_bytes: u64) -> Self {
+    self.directory = self.directory.map(|d| d.with_max_file_size_bytes(max_file_size_bytes));
+    self
+  }
+
+  /// Return the path of the credential for the item, if `$CREDENTIALS_DIRECTORY` is set.
+  pub fn path(&self, ci: &dyn ConfigurationItem) -> Option<PathBuf> {
+    self.directory.as_ref().map(|d| d.path(ci))
+  }
+}
+
+impl ConfigurationValueSource for Credentials {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    match &self.directory {
+      Some(directory) => directory.try_get(ci),
+      None => Some(Box::new(Error::DirectoryNotSet { name: ci.get_name().into() })),
+    }
+  }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    match self.path(ci) {
+      Some(path) => format!("systemd credential {}", path.display()),
+      None => format!("systemd credential for {} (no {})", ci.get_name(), CREDENTIALS_DIRECTORY),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+  use crate::testing::TempDir;
+
+  config!(
+    #[group = "database"]
+    #[secret]
+    (Password String)
+  );
+
+  #[test]
+  fn credentials_are_read_from_the_directory() {
+    let dir = TempDir::new("credentials");
+    std::fs::write(dir.join("database-password"), "hunter2").unwrap();
+
+    let credentials = Credentials::new(&dir).with_naming(Naming::Kebab);
+    let mut password = Password::new(None);
+    assert!(credentials.try_get(&mut password).is_none());
+    assert_eq!(password.get(), Some(&String::from("hunter2")));
+    assert_eq!(credentials.describe(&password), format!("systemd credential {}", dir.join("database-password").display()));
+
+    let e = Credentials::new(&dir).try_get(&mut password).unwrap();
+    assert_eq!(e.to_string(), format!("File ({}) for (Password) not found.", dir.join("database.password").display()));
+  }
+
+  #[test]
+  fn unset_directory_is_reported() {
+    let credentials = Credentials { directory: None }.with_naming(Naming::Kebab);
+    let mut password = Password::new(None);
+    let e = credentials.try_get(&mut password).unwrap();
+    assert_eq!(e.to_string(), "Credential for (Password) not available: CREDENTIALS_DIRECTORY is not set; is the service started by systemd with LoadCredential= or SetCredential=?");
+    assert_eq!(credentials.describe(&password), "systemd credential for Password (no CREDENTIALS_DIRECTORY)");
+  }
+}
@@ -0,0 +1,50 @@
+//! Source of configuration values that uses the defaults declared on each item.
+
+use crate::ConfigurationItem;
+use crate::ConfigurationValueSource;
+
+/// Provides the declared default for each item.
+/// Usually the last source in a `ConfigurationStrategy`.
+#[derive(Debug, Default)]
+pub struct Defaults;
+
+impl ConfigurationValueSource for Defaults {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    ci.try_default()
+  }
+
+  fn describe(&self, _ci: &dyn ConfigurationItem) -> String {
+    String::from("default")
+  }
+}
+
+pub fn new() -> Defaults {
+  Defaults
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+
+  config!(
+    #[default = "spline"]
+    (DefaultedItem String),
+    (UndefaultedItem String)
+  );
+
+  #[test]
+  fn uses_declared_default() {
+    let mut ci = DefaultedItem::new(None);
+    assert!(Defaults.try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&String::from("spline")));
+  }
+
+  #[test]
+  fn fails_without_declared_default() {
+    let mut ci = UndefaultedItem::new(None);
+    assert!(Defaults.try_get(&mut ci).is_some());
+    assert_eq!(ci.get(), None);
+  }
+}
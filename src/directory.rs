@@ -0,0 +1,249 @@
+//! Source of configuration values read from a directory with one file per item, as Kubernetes
+//! mounts ConfigMaps and Secrets, and Docker and Compose mount secrets (`/run/secrets`).
+//!
+//! Files are read when an item is looked up, so a ConfigMap updated in place is seen by later
+//! lookups. Kubernetes mounts each key as a symlink through `..data`, which points at a hidden,
+//! timestamped directory; the symlinks are followed, and the hidden entries themselves are never
+//! treated as keys.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use convert_case::Case::Kebab;
+use convert_case::Case::UpperSnake;
+use convert_case::Casing;
+
+use crate::dump;
+use crate::file;
+use crate::schema;
+use crate::ConfigurationItem;
+use crate::ConfigurationValueSource;
+
+/// Files larger than this are not read, unless another limit is specified.
+/// Kubernetes limits a whole ConfigMap to 1 MiB.
+pub const MAX_FILE_SIZE_BYTES: u64 = 1024 * 1024;
+
+/// How the file name for an item is chosen.
+#[derive(Debug, Clone, Copy)]
+pub enum Naming {
+  /// The key used in configuration files, e.g. `port` or `database.host`.
+  FileKey,
+  /// Kebab case, with the group first, e.g. `port` or `database-host`.
+  Kebab,
+  /// Upper snake case, with the group first, e.g. `PORT` or `DATABASE_HOST`.
+  UpperSnake,
+  /// Any other mapping.
+  Custom(fn(&dyn ConfigurationItem) -> String),
+}
+
+impl Naming {
+  pub fn file_name(&self, ci: &dyn ConfigurationItem) -> String {
+    let joined = || match ci.get_group() {
+      Some(group) => format!("{} {}", group, ci.get_name()),
+      None => ci.get_name().to_string(),
+    };
+    match self {
+      Naming::FileKey => schema::file_key(ci),
+      Naming::Kebab => joined().to_case(Kebab),
+      Naming::UpperSnake => joined().to_case(UpperSnake),
+      Naming::Custom(f) => f(ci),
+    }
+  }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+  #[error("File ({path}) for ({name}) not found.")]
+  FileNotFound {
+    name: String,
+    path: PathBuf,
+  },
+  #[error("Could not read file ({path}) for ({name}): {reason}.")]
+  CouldNotRead {
+    name: String,
+    path: PathBuf,
+    reason: String,
+  },
+  #[error("File ({path}) for ({name}) is not valid unicode.")]
+  NotUnicode {
+    name: String,
+    path: PathBuf,
+  },
+  #[error("Value in file ({path}={value}) was not accepted. {hint}")]
+  ValueNotAccepted {
+    name: String,
+    path: PathBuf,
+    value: String,
+    hint: String,
+    source: Box<dyn std::error::Error>,
+  },
+}
+
+/// A directory where each file name is a key, and the file's contents are the value.
+/// Trailing newlines are removed from values.
+#[derive(Debug)]
+pub struct Directory {
+  dir: PathBuf,
+  naming: Naming,
+  max_file_size_bytes: u64,
+}
+
+impl Directory {
+  /// Read files in `dir`, named by `Naming::FileKey`.
+  pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+    Self {
+      dir: dir.as_ref().to_path_buf(),
+      naming: Naming::FileKey,
+      max_file_size_bytes: MAX_FILE_SIZE_BYTES,
+    }
+  }
+
+  /// Choose how the file for an item is named. Defaults to `Naming::FileKey`.
+  pub fn with_naming(mut self, naming: Naming) -> Self {
+    self.naming = naming;
+    self
+  }
+
+  /// Files larger than this are not read. Defaults to `MAX_FILE_SIZE_BYTES`.
+  pub fn with_max_file_size_bytes(mut self, max_file_size_bytes: u64) -> Self {
+    self.max_file_size_bytes = max_file_size_bytes;
+    self
+  }
+
+  /// Return the path of the file this source reads for the item.
+  pub fn path(&self, ci: &dyn ConfigurationItem) -> PathBuf {
+    self.dir.join(self.naming.file_name(ci))
+  }
+
+  /// Return the keys in the directory, in lexical order, skipping hidden entries such as `..data`.
+  pub fn keys(&self) -> Vec<String> {
+    let mut keys: Vec<String> = match fs::read_dir(&self.dir) {
+      Err(_) => Vec::new(),
+      Ok(entries) => entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect(),
+    };
+    keys.sort();
+    keys
+  }
+}
+
+impl ConfigurationValueSource for Directory {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    let ci_name = String::from(ci.get_name());
+    let name = self.naming.file_name(ci);
+    let path = self.dir.join(&name);
+
+    use self::Error::*;
+    // hidden entries belong to the mount, not the configuration
+    if name.starts_with('.') || !path.exists() {
+      return Some(Box::new(FileNotFound { name: ci_name, path: path }));
+    }
+
+    let f = file::File::new(&path, self.max_file_size_bytes);
+    let contents = match f.contents() {
+      Some(bytes) => bytes,
+      None => return Some(Box::new(CouldNotRead { name: ci_name, path: path, reason: f.result().to_string() })),
+    };
+    let value = match std::str::from_utf8(contents) {
+      Ok(s) => file::trim_newlines(s),
+      Err(_) => return Some(Box::new(NotUnicode { name: ci_name, path: path })),
+    };
+
+    match ci.try_value(&Box::new(value.to_string())) {
+      Some(e) => Some(Box::new(ValueNotAccepted {
+        name: ci_name,
+        path: path,
+        value: if ci.is_secret() { dump::REDACTED.into() } else { value.into() },
+        hint: crate::metadata::hint(ci, e.as_ref()),
+        source: e,
+      })),
+      None => None,
+    }
+  }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    format!("file {}", self.path(ci).display())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+  use crate::testing::TempDir;
+
+  config!(
+    (Port u16),
+    #[group = "database"]
+    #[secret]
+    (Password String),
+    (Missing String)
+  );
+
+  #[test]
+  fn files_are_values() {
+    let dir = TempDir::new("directory");
+    std::fs::write(dir.join("port"), "8080\n").unwrap();
+    std::fs::write(dir.join("database.password"), "hunter2\r\n").unwrap();
+
+    let d = Directory::new(&dir);
+    let mut port = Port::new(None);
+    assert!(d.try_get(&mut port).is_none());
+    assert_eq!(port.get(), Some(&8080));
+    assert_eq!(d.describe(&port), format!("file {}", dir.join("port").display()));
+
+    let mut password = Password::new(None);
+    assert!(d.try_get(&mut password).is_none());
+    assert_eq!(password.get(), Some(&String::from("hunter2")));
+
+    let mut missing = Missing::new(None);
+    let e = d.try_get(&mut missing).unwrap();
+    assert_eq!(e.to_string(), format!("File ({}) for (Missing) not found.", dir.join("missing").display()));
+
+    let d = Directory::new(&dir).with_max_file_size_bytes(2);
+    let e = d.try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), format!("Could not read file ({}) for (Port): file is too large (5 bytes).", dir.join("port").display()));
+  }
+
+  #[test]
+  fn naming_is_configurable() {
+    let password = Password::new(None);
+    assert_eq!(Naming::FileKey.file_name(&password), "database.password");
+    assert_eq!(Naming::Kebab.file_name(&password), "database-password");
+    assert_eq!(Naming::UpperSnake.file_name(&password), "DATABASE_PASSWORD");
+    assert_eq!(Naming::Custom(|ci| format!("myapp_{}", ci.get_name().to_lowercase())).file_name(&password), "myapp_password");
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn kubernetes_symlinks_are_followed() {
+    let dir = TempDir::new("directory-k8s");
+    let version = dir.join("..2024_01_01_00_00_00.1");
+    std::fs::create_dir_all(&version).unwrap();
+    std::fs::write(version.join("port"), "not a port").unwrap();
+    std::os::unix::fs::symlink("..2024_01_01_00_00_00.1", dir.join("..data")).unwrap();
+    std::os::unix::fs::symlink("..data/port", dir.join("port")).unwrap();
+
+    let d = Directory::new(&dir);
+    assert_eq!(d.keys(), vec![String::from("port")]);
+
+    let mut port = Port::new(None);
+    let e = d.try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), format!("Value in file ({}=not a port) was not accepted. Expected a value of type integer.", dir.join("port").display()));
+
+    // the value is replaced by swapping `..data`, as the kubelet does
+    let next = dir.join("..2024_01_02_00_00_00.2");
+    std::fs::create_dir_all(&next).unwrap();
+    std::fs::write(next.join("port"), "443\n").unwrap();
+    std::os::unix::fs::symlink("..2024_01_02_00_00_00.2", dir.join("..data_tmp")).unwrap();
+    std::fs::rename(dir.join("..data_tmp"), dir.join("..data")).unwrap();
+    assert!(d.try_get(&mut port).is_none());
+    assert_eq!(port.get(), Some(&443));
+  }
+}
@@ -0,0 +1,235 @@
+//! Finding configuration files in the standard places.
+//!
+//! For an application called `myapp`, the candidates are, by default and highest priority first:
+//!
+//! - `.myapp.toml` in the working directory or the nearest parent directory containing one;
+//! - `$XDG_CONFIG_HOME/myapp/config.toml` (`$XDG_CONFIG_HOME` defaults to `~/.config`);
+//! - `<dir>/myapp/config.toml` for each directory in `$XDG_CONFIG_DIRS` (defaults to `/etc/xdg`);
+//! - `/etc/myapp/config.toml`.
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::config_file::ConfigurationFile;
+
+/// A place configuration files are looked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+  /// `.<app>.toml` in the working directory or one of its parents.
+  Project,
+  /// `$XDG_CONFIG_HOME/<app>/`.
+  XdgConfigHome,
+  /// `<dir>/<app>/` for each directory in `$XDG_CONFIG_DIRS`.
+  XdgConfigDirs,
+  /// `/etc/<app>/`.
+  Etc,
+}
+
+impl fmt::Display for Location {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Location::Project => "project",
+      Location::XdgConfigHome => "XDG_CONFIG_HOME",
+      Location::XdgConfigDirs => "XDG_CONFIG_DIRS",
+      Location::Etc => "/etc",
+    })
+  }
+}
+
+/// A path that was checked for a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+  pub location: Location,
+  pub path: PathBuf,
+  pub found: bool,
+}
+
+/// Describes where to look for configuration files.
+#[derive(Debug, Clone)]
+pub struct Discovery {
+  app: String,
+  file_name: String,
+  order: Vec<Location>,
+  vars: Option<HashMap<String, String>>,
+  current_dir: Option<PathBuf>,
+  etc: PathBuf,
+}
+
+impl Discovery {
+  pub fn new(app: &str) -> Self {
+    Self {
+      app: app.into(),
+      file_name: String::from("config.toml"),
+      order: vec![Location::Project, Location::XdgConfigHome, Location::XdgConfigDirs, Location::Etc],
+      vars: None,
+      current_dir: None,
+      etc: PathBuf::from("/etc"),
+    }
+  }
+
+  /// Look for this file name in the application's directories. Defaults to `config.toml`.
+  pub fn with_file_name(mut self, file_name: &str) -> Self {
+    self.file_name = file_name.into();
+    self
+  }
+
+  /// Look in these locations only, highest priority first.
+  pub fn with_order(mut self, order: Vec<Location>) -> Self {
+    self.order = order;
+    self
+  }
+
+  /// Use these variables instead of the process environment, e.g. for tests.
+  pub fn with_vars(mut self, vars: Vec<(String, String)>) -> Self {
+    self.vars = Some(vars.into_iter().collect());
+    self
+  }
+
+  /// Start looking for project files here, instead of the working directory.
+  pub fn with_current_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+    self.current_dir = Some(dir.as_ref().to_path_buf());
+    self
+  }
+
+  /// Use this directory instead of `/etc`.
+  pub fn with_etc<P: AsRef<Path>>(mut self, dir: P) -> Self {
+    self.etc = dir.as_ref().to_path_buf();
+    self
+  }
+
+  fn var(&self, name: &str) -> Option<String> {
+    let value = match &self.vars {
+      Some(vars) => vars.get(name).cloned(),
+      None => env::var(name).ok(),
+    };
+    value.filter(|v| !v.is_empty())
+  }
+
+  fn candidates_in(&self, location: Location) -> Vec<Candidate> {
+    let in_app_dir = |dir: &Path| dir.join(&self.app).join(&self.file_name);
+    let paths = match location {
+      Location::Project => return self.project_candidates(),
+      Location::XdgConfigHome => match (self.var("XDG_CONFIG_HOME"), self.var("HOME")) {
+        (Some(dir), _) => vec![in_app_dir(Path::new(&dir))],
+        (None, Some(home)) => vec![in_app_dir(&Path::new(&home).join(".config"))],
+        (None, None) => Vec::new(),
+      },
+      Location::XdgConfigDirs => self.var("XDG_CONFIG_DIRS")
+        .unwrap_or_else(|| String::from("/etc/xdg"))
+        .split(':')
+        .filter(|d| !d.is_empty())
+        .map(|d| in_app_dir(Path::new(d)))
+        .collect(),
+      Location::Etc => vec![in_app_dir(&self.etc)],
+    };
+
+    paths.into_iter()
+      .map(|p| Candidate { location: location, found: p.is_file(), path: p })
+      .collect()
+  }
+
+  // walk up from the working directory, stopping at the first file found
+  fn project_candidates(&self) -> Vec<Candidate> {
+    let start = match self.current_dir.clone().or_else(|| env::current_dir().ok()) {
+      Some(dir) => dir,
+      None => return Vec::new(),
+    };
+
+    let file_name = format!(".{}.toml", self.app);
+    let mut candidates = Vec::new();
+    for dir in start.ancestors() {
+      let path = dir.join(&file_name);
+      let found = path.is_file();
+      candidates.push(Candidate { location: Location::Project, path: path, found: found });
+      if found { break; }
+    }
+    candidates
+  }
+
+  /// Check every candidate path, in order.
+  pub fn discover(&self) -> Discovered {
+    Discovered {
+      candidates: self.order.iter().flat_map(|l| self.candidates_in(*l)).collect(),
+    }
+  }
+}
+
+/// The result of looking for configuration files.
+#[derive(Debug, Clone)]
+pub struct Discovered {
+  candidates: Vec<Candidate>,
+}
+
+impl Discovered {
+  /// Return every path that was checked, highest priority first.
+  pub fn candidates(&self) -> &[Candidate] {
+    &self.candidates
+  }
+
+  /// Return the files that were found, highest priority first.
+  pub fn found(&self) -> Vec<&Path> {
+    self.candidates.iter().filter(|c| c.found).map(|c| c.path.as_path()).collect()
+  }
+
+  /// Load the files that were found, highest priority first.
+  pub fn sources(&self) -> Vec<ConfigurationFile> {
+    self.found().into_iter().map(ConfigurationFile::new).collect()
+  }
+
+  /// List every path that was checked and whether a file was found there.
+  pub fn report(&self) -> String {
+    let mut out = String::from("configuration files:\n");
+    for c in self.candidates.iter() {
+      out.push_str(&format!("  {} ({}): {}\n", c.path.display(), c.location, if c.found { "found" } else { "not found" }));
+    }
+    out
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::testing::TempDir;
+
+  #[test]
+  fn every_candidate_is_reported() {
+    let root = TempDir::new("discovery");
+    let project = root.join("project/src");
+    std::fs::create_dir_all(&project).unwrap();
+    std::fs::create_dir_all(root.join("etc/myapp")).unwrap();
+    std::fs::write(root.join("project/.myapp.toml"), "").unwrap();
+    std::fs::write(root.join("etc/myapp/config.toml"), "").unwrap();
+
+    let discovered = Discovery::new("myapp")
+      .with_vars(vec![
+        ("HOME".into(), root.join("home").to_string_lossy().into()),
+        ("XDG_CONFIG_DIRS".into(), format!("{}:{}", root.join("xdg1").display(), root.join("xdg2").display())),
+      ])
+      .with_current_dir(&project)
+      .with_etc(root.join("etc"))
+      .discover();
+
+    assert_eq!(discovered.found(), vec![root.join("project/.myapp.toml"), root.join("etc/myapp/config.toml")]);
+    let report = discovered.report().replace(&root.display().to_string(), "ROOT");
+    assert_eq!(report, concat!(
+      "configuration files:\n",
+      "  ROOT/project/src/.myapp.toml (project): not found\n",
+      "  ROOT/project/.myapp.toml (project): found\n",
+      "  ROOT/home/.config/myapp/config.toml (XDG_CONFIG_HOME): not found\n",
+      "  ROOT/xdg1/myapp/config.toml (XDG_CONFIG_DIRS): not found\n",
+      "  ROOT/xdg2/myapp/config.toml (XDG_CONFIG_DIRS): not found\n",
+      "  ROOT/etc/myapp/config.toml (/etc): found\n",
+    ));
+
+    let discovered = Discovery::new("myapp")
+      .with_vars(vec![])
+      .with_order(vec![Location::Etc])
+      .with_etc(root.join("etc"))
+      .discover();
+    assert_eq!(discovered.sources().len(), 1);
+  }
+}
@@ -0,0 +1,225 @@
+//! Generate reference documentation for a set of configuration items.
+
+use crate::ConfigurationItem;
+use crate::command_line;
+use crate::constraint::Constraint;
+use crate::environment::Environment;
+use crate::metadata::Visibility;
+use crate::schema;
+use crate::value::Kind;
+
+/// Everything the reference documentation says about a single item.
+#[derive(Debug)]
+pub struct Entry {
+  pub key: String,
+  pub envvar: String,
+  pub flag: String,
+  pub kind: String,
+  pub default: Option<String>,
+  pub allowed_values: Option<Vec<String>>,
+  pub constraints: Vec<String>,
+  pub secret: bool,
+  pub description: Option<String>,
+  pub examples: Vec<String>,
+  pub units: Option<&'static str>,
+  pub since: Option<&'static str>,
+  pub visibility: Visibility,
+}
+
+impl Entry {
+  pub fn new(env: &Environment, ci: &dyn ConfigurationItem) -> Self {
+    let kind = ci.get_kind();
+    let metadata = ci.get_metadata();
+    let constraints = ci.get_constraints();
+    let one_of = constraints.iter().find_map(|c| match c {
+      Constraint::OneOf(values) => Some(values.iter().map(|v| v.to_json_inline()).collect()),
+      _ => None,
+    });
+    Self {
+      key: schema::file_key(ci),
+      envvar: env.envvar_name(ci),
+      flag: command_line::flag_name(ci),
+      kind: kind.name(),
+      default:
+        if ci.is_secret() { ci.get_default().map(|_| "(secret)".to_string()) }
+        else { ci.get_default().map(|d| d.to_json_inline()) },
+      allowed_values: match (one_of, kind) {
+        (Some(values), _) => Some(values),
+        (None, Kind::Boolean) => Some(vec!["true".into(), "false".into()]),
+        (None, Kind::Enum(choices)) => Some(choices),
+        (None, _) => None,
+      },
+      constraints: constraints.iter()
+        .filter(|c| !matches!(c, Constraint::OneOf(_)))
+        .map(|c| c.describe())
+        .collect(),
+      secret: ci.is_secret(),
+      description: metadata.description,
+      examples: metadata.examples.iter().map(|e| e.to_json_inline()).collect(),
+      units: metadata.units,
+      since: metadata.since,
+      visibility: metadata.visibility,
+    }
+  }
+}
+
+/// Return the entries for the items that should be documented.
+pub fn entries(env: &Environment, items: &[&dyn ConfigurationItem]) -> Vec<Entry> {
+  items.iter()
+    .map(|ci| Entry::new(env, *ci))
+    .filter(|e| e.visibility != Visibility::Hidden)
+    .collect()
+}
+
+/// Return the reference documentation for the items as Markdown.
+/// Hidden items are left out.
+pub fn markdown(env: &Environment, items: &[&dyn ConfigurationItem]) -> String {
+  let mut out = String::from("# Configuration reference\n");
+
+  for entry in entries(env, items) {
+    let advanced = if entry.visibility == Visibility::Advanced { " (advanced)" } else { "" };
+    out.push_str(&format!("\n## `{}`{}\n\n", entry.key, advanced));
+
+    if let Some(description) = &entry.description {
+      out.push_str(description);
+      out.push_str("\n\n");
+    }
+
+    out.push_str("| | |\n|---|---|\n");
+    let mut row = |name: &str, value: &str| out.push_str(&format!("| {} | {} |\n", name, value.replace('|', "\\|")));
+    row("Environment variable", &format!("`{}`", entry.envvar));
+    row("Command-line flag", &format!("`{}`", entry.flag));
+    row("File key", &format!("`{}`", entry.key));
+    row("Type", &entry.kind);
+    if let Some(units) = entry.units {
+      row("Units", units);
+    }
+    if let Some(default) = &entry.default {
+      row("Default", &format!("`{}`", default));
+    }
+    if !entry.examples.is_empty() {
+      row("Examples", &entry.examples.iter().map(|v| format!("`{}`", v)).collect::<Vec<_>>().join(", "));
+    }
+    if let Some(allowed) = &entry.allowed_values {
+      row("Allowed values", &allowed.iter().map(|v| format!("`{}`", v)).collect::<Vec<_>>().join(", "));
+    }
+    if !entry.constraints.is_empty() {
+      row("Constraints", &entry.constraints.join(", "));
+    }
+    row("Secret", if entry.secret { "yes" } else { "no" });
+    if let Some(since) = entry.since {
+      row("Since", since);
+    }
+  }
+
+  out
+}
+
+fn roff_escape(s: &str) -> String {
+  let escaped = s.replace('\\', "\\e").replace('-', "\\-");
+  // lines beginning with a control character would be interpreted as requests
+  escaped.lines()
+    .map(|l| if l.starts_with('.') || l.starts_with('\'') { format!("\\&{}", l) } else { l.to_string() })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// Return the reference documentation for the items as a man page section, to be included in the
+/// application's man page. Hidden items are left out.
+pub fn man_section(env: &Environment, items: &[&dyn ConfigurationItem]) -> String {
+  let mut out = String::from(".SH CONFIGURATION\n");
+
+  for entry in entries(env, items) {
+    out.push_str(&format!(".TP\n.B {}\n", roff_escape(&entry.key)));
+    if entry.visibility == Visibility::Advanced {
+      out.push_str("(advanced)\n.br\n");
+    }
+
+    if let Some(description) = &entry.description {
+      out.push_str(&roff_escape(description));
+      out.push_str("\n.br\n");
+    }
+
+    out.push_str(&format!("Environment variable: \\fB{}\\fR\n.br\n", roff_escape(&entry.envvar)));
+    out.push_str(&format!("Command\\-line flag: \\fB{}\\fR\n.br\n", roff_escape(&entry.flag)));
+    out.push_str(&format!("Type: {}\n", entry.kind));
+    if let Some(units) = entry.units {
+      out.push_str(&format!(".br\nUnits: {}\n", roff_escape(units)));
+    }
+    if let Some(default) = &entry.default {
+      out.push_str(&format!(".br\nDefault: {}\n", roff_escape(default)));
+    }
+    if !entry.examples.is_empty() {
+      out.push_str(&format!(".br\nExamples: {}\n", roff_escape(&entry.examples.join(", "))));
+    }
+    if let Some(allowed) = &entry.allowed_values {
+      out.push_str(&format!(".br\nAllowed values: {}\n", roff_escape(&allowed.join(", "))));
+    }
+    if !entry.constraints.is_empty() {
+      out.push_str(&format!(".br\nConstraints: {}\n", roff_escape(&entry.constraints.join(", "))));
+    }
+    if entry.secret {
+      out.push_str(".br\nThis value is secret.\n");
+    }
+    if let Some(since) = entry.since {
+      out.push_str(&format!(".br\nSince: {}\n", roff_escape(since)));
+    }
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+
+  config!(
+    /// How many splines to reticulate at once.
+    #[default = 4.0]
+    #[min = 0.0]
+    (DocsThreshold f64),
+
+    #[group = "database"]
+    #[secret]
+    #[default = "hunter2"]
+    (DocsPassword String),
+
+    #[hidden]
+    (DocsInternal String)
+  );
+
+  #[test]
+  fn markdown_lists_every_name() {
+    let env = Environment::new("APPNAME".into(), Vec::new());
+    let doc = markdown(&env, &[&DocsThreshold::new(None), &DocsPassword::new(None)]);
+
+    assert!(doc.contains("## `docs_threshold`\n\nHow many splines to reticulate at once.\n"));
+    assert!(doc.contains("| Environment variable | `APPNAME_DOCS_THRESHOLD` |"));
+    assert!(doc.contains("| Command-line flag | `--docs-threshold` |"));
+    assert!(doc.contains("| Default | `4.0` |"));
+    assert!(doc.contains("| Constraints | must be at least 0.0 |"));
+    assert!(doc.contains("| File key | `database.docs_password` |"));
+    assert!(doc.contains("| Environment variable | `APPNAME__database__DOCS_PASSWORD` |"));
+  }
+
+  #[test]
+  fn hidden_items_are_not_documented() {
+    let env = Environment::new("APPNAME".into(), Vec::new());
+    let doc = markdown(&env, &[&DocsThreshold::new(None), &DocsInternal::new(None)]);
+    assert!(!doc.contains("docs_internal"));
+  }
+
+  #[test]
+  fn secret_defaults_are_not_shown() {
+    let env = Environment::new("APPNAME".into(), Vec::new());
+    let doc = markdown(&env, &[&DocsPassword::new(None)]);
+    assert!(!doc.contains("hunter2"));
+    assert!(doc.contains("| Secret | yes |"));
+
+    let man = man_section(&env, &[&DocsPassword::new(None)]);
+    assert!(!man.contains("hunter2"));
+    assert!(man.contains("\\fB\\-\\-database\\-docs\\-password\\fR"));
+  }
+}
@@ -0,0 +1,264 @@
+//! Serialize the resolved configuration, noting where each value came from.
+
+use std::collections::BTreeMap;
+
+use crate::Attempts;
+use crate::schema;
+use crate::value::Value;
+use crate::value::json_string;
+
+/// Formats the configuration can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Toml,
+  /// JSON has no comments, so provenance is written to a separate `$provenance` object.
+  Json,
+  Yaml,
+}
+
+/// Written in place of the values of secret items.
+pub const REDACTED: &str = "<redacted>";
+
+/// The resolved value of a single item, and where it came from.
+#[derive(Debug)]
+struct Resolved {
+  key: String,
+  value: Option<Value>,
+  provenance: String,
+}
+
+impl Resolved {
+  fn new(attempts: &Attempts) -> Self {
+    let item = attempts.item();
+    let value = item.get_value();
+    Self {
+      key: schema::property_name(item),
+      value:
+        if attempts.is_secret() { value.map(|_| Value::String(REDACTED.into())) }
+        else { value },
+      provenance: match (attempts.provenance(), attempts.unset()) {
+        (Some(p), _) => format!("from {}", p),
+        (None, Some(a)) => format!("unset by {}", a.source().describe(item)),
+        (None, None) => String::from("not set"),
+      },
+    }
+  }
+}
+
+/// Split the items into those without a group, and each group in order of first appearance.
+fn grouped(results: &[Attempts]) -> (Vec<Resolved>, Vec<(String, Vec<Resolved>)>) {
+  let mut ungrouped = Vec::new();
+  let mut groups: Vec<(String, Vec<Resolved>)> = Vec::new();
+
+  for attempts in results {
+    let resolved = Resolved::new(attempts);
+    match attempts.item().get_group() {
+      None => ungrouped.push(resolved),
+      Some(group) => match groups.iter_mut().find(|(g, _)| g == group) {
+        Some((_, items)) => items.push(resolved),
+        None => groups.push((group.to_string(), vec![resolved])),
+      },
+    }
+  }
+
+  (ungrouped, groups)
+}
+
+// plain scalars YAML 1.1 reads as booleans or null, compared ignoring case
+const YAML_RESERVED: &[&str] = &["y", "n", "yes", "no", "true", "false", "on", "off", "null", "~"];
+
+/// TOML bare keys and YAML plain scalars both allow these keys to be written unquoted.
+/// Keys YAML would read as something other than a string, like `on`, `no` or `1`, are quoted.
+pub(crate) fn key(key: &str) -> String {
+  let plain = key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+  let reserved = YAML_RESERVED.contains(&key.to_ascii_lowercase().as_str());
+  let numeric = key.starts_with(|c: char| c.is_ascii_digit() || c == '-');
+  if !key.is_empty() && plain && !reserved && !numeric {
+    key.to_string()
+  } else {
+    json_string(key)
+  }
+}
+
+pub(crate) fn toml_value(value: &Value) -> String {
+  match value {
+    Value::Number(n) if n.is_nan() => "nan".into(),
+    Value::Number(n) if n.is_infinite() => if *n > 0.0 { "inf".into() } else { "-inf".into() },
+    Value::List(items) => format!("[{}]", items.iter().map(toml_value).collect::<Vec<_>>().join(", ")),
+    Value::Map(entries) => format!("{{ {} }}", entries.iter()
+      .map(|(k, v)| format!("{} = {}", key(k), toml_value(v)))
+      .collect::<Vec<_>>()
+      .join(", ")),
+    _ => value.to_json(),
+  }
+}
+
+pub(crate) fn yaml_value(value: &Value) -> String {
+  match value {
+    Value::Number(n) if n.is_nan() => ".nan".into(),
+    Value::Number(n) if n.is_infinite() => if *n > 0.0 { ".inf".into() } else { "-.inf".into() },
+    // JSON is valid YAML flow style
+    _ => value.to_json_inline(),
+  }
+}
+
+fn line(out: &mut String, indent: &str, key: &str, value: &Option<Value>, provenance: &str, format: Format) {
+  let key = self::key(key);
+  let value = match format {
+    Format::Toml => value.as_ref().map(toml_value),
+    _ => value.as_ref().map(yaml_value),
+  };
+  let separator = if format == Format::Toml { " = " } else { ": " };
+
+  match value {
+    Some(v) => out.push_str(&format!("{}{}{}{}  # {}\n", indent, key, separator, v, provenance)),
+    None => out.push_str(&format!("{}# {} is {}\n", indent, key, provenance)),
+  }
+}
+
+fn insert(values: &mut BTreeMap<String, Value>, provenance: &mut BTreeMap<String, Value>, r: Resolved) {
+  if let Some(v) = r.value { values.insert(r.key.clone(), v); }
+  provenance.insert(r.key, Value::String(r.provenance));
+}
+
+fn json(results: &[Attempts]) -> String {
+  let (ungrouped, groups) = grouped(results);
+  let mut values = BTreeMap::new();
+  let mut provenance = BTreeMap::new();
+
+  for r in ungrouped {
+    insert(&mut values, &mut provenance, r);
+  }
+
+  for (group, items) in groups {
+    let mut group_values = BTreeMap::new();
+    let mut group_provenance = BTreeMap::new();
+    for r in items {
+      insert(&mut group_values, &mut group_provenance, r);
+    }
+    values.insert(group.clone(), Value::Map(group_values));
+    provenance.insert(group, Value::Map(group_provenance));
+  }
+
+  values.insert("$provenance".into(), Value::Map(provenance));
+  Value::Map(values).to_json() + "\n"
+}
+
+/// Serialize the resolved configuration in the specified format.
+/// Each value is annotated with the source it came from; values of secret items are redacted.
+pub fn dump(results: &[Attempts], format: Format) -> String {
+  if format == Format::Json {
+    return json(results);
+  }
+
+  let (ungrouped, groups) = grouped(results);
+  let mut out = String::new();
+
+  for r in ungrouped.iter() {
+    line(&mut out, "", &r.key, &r.value, &r.provenance, format);
+  }
+
+  for (group, items) in groups.iter() {
+    match format {
+      Format::Toml => out.push_str(&format!("\n[{}]\n", key(group))),
+      _ => out.push_str(&format!("{}:\n", key(group))),
+    }
+    let indent = if format == Format::Toml { "" } else { "  " };
+    for r in items.iter() {
+      line(&mut out, indent, &r.key, &r.value, &r.provenance, format);
+    }
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+  use crate::ConfigurationStrategy;
+  use crate::ConfigurationValueSource;
+  use crate::defaults::Defaults;
+  use crate::environment::Environment;
+
+  config!(
+    #[default = 1.0]
+    (MyThreshold f64),
+
+    #[group = "database"]
+    #[default = "localhost"]
+    (Host String),
+
+    #[group = "database"]
+    #[secret]
+    (Password String),
+
+    (Unset String)
+  );
+
+  fn render(format: Format) -> String {
+    let env = Environment::new("APPNAME".into(), vec![
+      ("APPNAME_MY_THRESHOLD".into(), Ok("4.0".into())),
+      ("APPNAME__database__PASSWORD".into(), Ok("hunter2".into())),
+    ]);
+    let defaults = Defaults;
+    let sources: Vec<&dyn ConfigurationValueSource> = vec![&env, &defaults];
+    let strategy = ConfigurationStrategy::new(sources);
+
+    let mut threshold = MyThreshold::new(None);
+    let mut host = Host::new(None);
+    let mut password = Password::new(None);
+    let mut unset = Unset::new(None);
+    let results = vec![
+      strategy.try_get(&mut threshold),
+      strategy.try_get(&mut host),
+      strategy.try_get(&mut password),
+      strategy.try_get(&mut unset),
+    ];
+
+    dump(&results, format)
+  }
+
+  #[test]
+  fn toml_notes_provenance() {
+    assert_eq!(render(Format::Toml), "\
+my_threshold = 4.0  # from env APPNAME_MY_THRESHOLD
+# unset is not set
+
+[database]
+host = \"localhost\"  # from default
+password = \"<redacted>\"  # from env APPNAME__database__PASSWORD
+");
+  }
+
+  #[test]
+  fn yaml_notes_provenance() {
+    assert_eq!(render(Format::Yaml), "\
+my_threshold: 4.0  # from env APPNAME_MY_THRESHOLD
+# unset is not set
+database:
+  host: \"localhost\"  # from default
+  password: \"<redacted>\"  # from env APPNAME__database__PASSWORD
+");
+  }
+
+  #[test]
+  fn keys_yaml_would_misread_are_quoted() {
+    assert_eq!(key("host"), "host");
+    assert_eq!(key("on"), "\"on\"");
+    assert_eq!(key("No"), "\"No\"");
+    assert_eq!(key("NULL"), "\"NULL\"");
+    assert_eq!(key("8080"), "\"8080\"");
+    assert_eq!(key("a.b"), "\"a.b\"");
+  }
+
+  #[test]
+  fn json_provenance_is_separate() {
+    let out = render(Format::Json);
+    assert!(out.contains("\"my_threshold\": 4.0\n"));
+    assert!(out.contains("\"my_threshold\": \"from env APPNAME_MY_THRESHOLD\""));
+    assert!(out.contains("\"unset\": \"not set\""));
+    assert!(!out.contains("hunter2"));
+  }
+}
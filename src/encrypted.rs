@@ -0,0 +1,393 @@
+//! Encrypted values, so configuration files holding secrets can be committed.
+//!
+//! A value written as `ENC[<algorithm>,<base64>]` is decrypted by a `Decryptor` before it reaches
+//! the item; other values are passed on unchanged. Wrap any source in `Decrypting` to decrypt the
+//! values it provides. Decrypted values are treated as secret, whether or not the item is (see
+//! `Attempts::is_secret`).
+//!
+//! The built-in `Aead` uses ChaCha20-Poly1305 with a 256-bit key, written in base64. Values are
+//! encrypted with `Aead::encrypt`, or with the `config-encrypt` command:
+//!
+//! ```text
+//! $ config-encrypt --generate-key > /etc/myapp/key
+//! $ echo -n hunter2 | config-encrypt --key-file /etc/myapp/key --key database.password
+//! ENC[chacha20poly1305,...]
+//! ```
+//!
+//! The value is bound to the item's key in configuration files, e.g. `database.password`, so it
+//! cannot be moved to another item and decrypted there.
+
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::Aead as _;
+use chacha20poly1305::aead::AeadCore;
+use chacha20poly1305::aead::KeyInit;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::Payload;
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::Nonce;
+
+use crate::constraint;
+use crate::file;
+use crate::merge;
+use crate::metadata;
+use crate::schema;
+use crate::value;
+use crate::value::Value;
+use crate::ConfigurationItem;
+use crate::ConfigurationValueSource;
+
+/// The name of the algorithm used by `Aead`, as written in encrypted values.
+pub const ALGORITHM: &str = "chacha20poly1305";
+
+// key files larger than this are not read
+const MAX_KEY_FILE_SIZE_BYTES: u64 = 1024;
+
+const NONCE_BYTES: usize = 12;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+  #[error("Encryption key is not valid: {reason}.")]
+  InvalidKey {
+    reason: String,
+  },
+  #[error("Could not read encryption key ({path}): {reason}.")]
+  KeyNotRead {
+    path: PathBuf,
+    reason: String,
+  },
+  #[error("Encryption key item ({name}) has no value.")]
+  KeyNotSet {
+    name: String,
+  },
+  #[error("Encrypted value is malformed: {reason}.")]
+  Malformed {
+    reason: String,
+  },
+  #[error("Encrypted value uses an unknown algorithm ({algorithm}); expected {ALGORITHM}.")]
+  UnknownAlgorithm {
+    algorithm: String,
+  },
+  #[error("Encrypted value could not be decrypted; it may have been encrypted with a different key, or for a different item.")]
+  NotAuthentic,
+  #[error("Value for ({name}) could not be decrypted. {reason}")]
+  NotDecrypted {
+    name: String,
+    reason: String,
+  },
+}
+
+/// Return the text between `ENC[` and `]`, if the value is encrypted.
+pub fn encrypted(s: &str) -> Option<&str> {
+  s.strip_prefix("ENC[")?.strip_suffix(']')
+}
+
+/// Decrypts values written as `ENC[...]`.
+pub trait Decryptor: fmt::Debug {
+  /// Decrypt the text between `ENC[` and `]`, for the item with this key in configuration files
+  /// (see `schema::file_key`).
+  fn decrypt(&self, encrypted: &str, key: &str) -> Result<String, Box<dyn std::error::Error>>;
+}
+
+/// ChaCha20-Poly1305 with a 256-bit key.
+pub struct Aead {
+  cipher: ChaCha20Poly1305,
+}
+
+// the key is not shown
+impl fmt::Debug for Aead {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Aead({})", ALGORITHM)
+  }
+}
+
+impl Aead {
+  /// Return a new random key, in base64.
+  pub fn generate_key() -> String {
+    BASE64.encode(ChaCha20Poly1305::generate_key(&mut OsRng))
+  }
+
+  /// Use the key, written in base64.
+  pub fn from_key(key: &str) -> Result<Self, Error> {
+    let bytes = BASE64.decode(key.trim())
+      .map_err(|e| Error::InvalidKey { reason: e.to_string() })?;
+    let cipher = ChaCha20Poly1305::new_from_slice(&bytes)
+      .map_err(|_| Error::InvalidKey { reason: format!("expected 32 bytes, found {}", bytes.len()) })?;
+    Ok(Self { cipher: cipher })
+  }
+
+  /// Read the key from a file, e.g. one written by `config-encrypt --generate-key`.
+  pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+    let path = path.as_ref();
+    let f = file::File::new(path, MAX_KEY_FILE_SIZE_BYTES);
+    match f.contents().map(std::str::from_utf8) {
+      None => Err(Error::KeyNotRead { path: path.to_path_buf(), reason: f.result().to_string() }),
+      Some(Err(_)) => Err(Error::KeyNotRead { path: path.to_path_buf(), reason: String::from("it is not valid unicode") }),
+      Some(Ok(key)) => Self::from_key(key),
+    }
+  }
+
+  /// Use the value of an item as the key, e.g. `APPNAME_ENCRYPTION_KEY` from the environment.
+  /// The item should be resolved first, without decryption.
+  pub fn from_item(ci: &dyn ConfigurationItem) -> Result<Self, Error> {
+    match ci.get_value().and_then(|v| v.as_scalar_string()) {
+      Some(key) => Self::from_key(&key),
+      None => Err(Error::KeyNotSet { name: ci.get_name().into() }),
+    }
+  }
+
+  /// Encrypt the value of the item with this key in configuration files, e.g.
+  /// `database.password`, returning `ENC[...]` to write in place of it.
+  pub fn encrypt(&self, key: &str, plaintext: &str) -> String {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = self.cipher.encrypt(&nonce, Payload { msg: plaintext.as_bytes(), aad: key.as_bytes() })
+      .expect("encrypting in memory does not fail");
+    let mut bytes = nonce.to_vec();
+    bytes.extend(ciphertext);
+    format!("ENC[{},{}]", ALGORITHM, BASE64.encode(bytes))
+  }
+}
+
+impl Decryptor for Aead {
+  fn decrypt(&self, encrypted: &str, key: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let (algorithm, data) = encrypted.split_once(',')
+      .ok_or_else(|| Error::Malformed { reason: String::from("expected ENC[<algorithm>,<data>]") })?;
+    if algorithm != ALGORITHM {
+      return Err(Box::new(Error::UnknownAlgorithm { algorithm: algorithm.into() }));
+    }
+
+    let bytes = BASE64.decode(data).map_err(|e| Error::Malformed { reason: e.to_string() })?;
+    if bytes.len() < NONCE_BYTES {
+      return Err(Box::new(Error::Malformed { reason: String::from("too short") }));
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_BYTES);
+    let plaintext = self.cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: key.as_bytes() })
+      .map_err(|_| Error::NotAuthentic)?;
+    String::from_utf8(plaintext)
+      .map_err(|_| Box::new(Error::Malformed { reason: String::from("the value is not valid unicode") }) as Box<dyn std::error::Error>)
+  }
+}
+
+/// A source whose `ENC[...]` values are decrypted before they are used.
+#[derive(Debug)]
+pub struct Decrypting<S> {
+  source: S,
+  decryptor: Box<dyn Decryptor>,
+  // keys of the items whose values were decrypted
+  decrypted: Mutex<BTreeSet<String>>,
+}
+
+impl<S> Decrypting<S> {
+  pub fn new<D: Decryptor + 'static>(source: S, decryptor: D) -> Self {
+    Self {
+      source: source,
+      decryptor: Box::new(decryptor),
+      decrypted: Mutex::new(BTreeSet::new()),
+    }
+  }
+
+  pub fn source(&self) -> &S {
+    &self.source
+  }
+}
+
+impl<S: ConfigurationValueSource> ConfigurationValueSource for Decrypting<S> {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    let key = schema::file_key(ci);
+    let mut proxy = Decrypt { inner: ci, key: key.clone(), decryptor: self.decryptor.as_ref(), decrypted: false, error: None };
+    let result = self.source.try_get(&mut proxy);
+
+    let mut decrypted = self.decrypted.lock().unwrap();
+    if proxy.decrypted {
+      decrypted.insert(key);
+    } else {
+      decrypted.remove(&key);
+    }
+    // the source would explain a decryption error as the value not being accepted
+    match proxy.error {
+      Some(e) => Some(e),
+      None => result,
+    }
+  }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    self.source.describe(ci)
+  }
+
+  fn is_secret(&self, ci: &dyn ConfigurationItem) -> bool {
+    self.decrypted.lock().unwrap().contains(&schema::file_key(ci)) || self.source.is_secret(ci)
+  }
+}
+
+// decrypts values before passing them on to the item
+#[derive(Debug)]
+struct Decrypt<'d> {
+  inner: &'d mut dyn ConfigurationItem,
+  // the item's key in configuration files, which values are bound to
+  key: String,
+  decryptor: &'d dyn Decryptor,
+  decrypted: bool,
+  error: Option<Box<dyn std::error::Error>>,
+}
+
+impl Decrypt<'_> {
+  fn decrypt_value(&mut self, value: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+    Ok(match value {
+      Value::String(s) => match encrypted(s) {
+        Some(encrypted) => {
+          self.decrypted = true;
+          Value::String(self.decryptor.decrypt(encrypted, &self.key)?)
+        }
+        None => value.clone(),
+      },
+      Value::List(items) => Value::List(items.iter()
+        .map(|v| self.decrypt_value(v))
+        .collect::<Result<_, _>>()?),
+      Value::Map(map) => Value::Map(map.iter()
+        .map(|(k, v)| Ok((k.clone(), self.decrypt_value(v)?)))
+        .collect::<Result<_, Box<dyn std::error::Error>>>()?),
+      other => other.clone(),
+    })
+  }
+}
+
+impl ConfigurationItem for Decrypt<'_> {
+  fn get_name(&self) -> &str { self.inner.get_name() }
+  fn get_group(&self) -> Option<&str> { self.inner.get_group() }
+  fn get_kind(&self) -> value::Kind { self.inner.get_kind() }
+  fn get_metadata(&self) -> metadata::Metadata { self.inner.get_metadata() }
+  fn is_secret(&self) -> bool { self.decrypted || self.inner.is_secret() }
+  fn get_merge(&self) -> merge::Merge { self.inner.get_merge() }
+  fn get_value(&self) -> Option<Value> { self.inner.get_value() }
+  fn get_default(&self) -> Option<Value> { self.inner.get_default() }
+  fn try_default(&mut self) -> Option<Box<dyn std::error::Error>> { self.inner.try_default() }
+  fn get_constraints(&self) -> Vec<constraint::Constraint> { self.inner.get_constraints() }
+
+  fn try_value(&mut self, value: &dyn Any) -> Option<Box<dyn std::error::Error>> {
+    let decrypted = if let Some(s) = value.downcast_ref::<Box<String>>() {
+      self.decrypt_value(&Value::String(s.to_string())).map(|v| match v {
+        Value::String(s) => Box::new(Box::new(s)) as Box<dyn Any>,
+        v => Box::new(Box::new(v)),
+      })
+    } else if let Some(v) = value.downcast_ref::<Box<Value>>() {
+      self.decrypt_value(v).map(|v| Box::new(Box::new(v)) as Box<dyn Any>)
+    } else {
+      return self.inner.try_value(value);
+    };
+
+    match decrypted {
+      // the error may show the value, which must not be shown once it has been decrypted
+      Ok(value) => match self.inner.try_value(value.as_ref()) {
+        Some(e) if self.decrypted => Some(constraint::redact(e.as_ref())),
+        other => other,
+      },
+      Err(e) => {
+        let name = String::from(self.inner.get_name());
+        let not_decrypted = || Box::new(Error::NotDecrypted { name: name.clone(), reason: e.to_string() });
+        self.error = Some(not_decrypted());
+        Some(not_decrypted())
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+  use crate::config_file::ConfigurationFile;
+  use crate::config_file::Format;
+  use crate::ConfigurationStrategy;
+  use crate::testing::TempDir;
+
+  config!(
+    #[group = "database"]
+    (Password String),
+    #[group = "database"]
+    (Host String),
+    (EncryptionKey String),
+    #[max_length = 4]
+    (Token String)
+  );
+
+  #[test]
+  fn values_round_trip() {
+    let aead = Aead::from_key(&Aead::generate_key()).unwrap();
+    let encrypted = aead.encrypt("token", "hunter2");
+    assert!(encrypted.starts_with("ENC[chacha20poly1305,"));
+    assert_ne!(encrypted, aead.encrypt("token", "hunter2"));
+    assert_eq!(aead.decrypt(super::encrypted(&encrypted).unwrap(), "token").unwrap(), "hunter2");
+
+    let other = Aead::from_key(&Aead::generate_key()).unwrap();
+    let e = other.decrypt(super::encrypted(&encrypted).unwrap(), "token").unwrap_err();
+    assert_eq!(e.to_string(), "Encrypted value could not be decrypted; it may have been encrypted with a different key, or for a different item.");
+
+    assert_eq!(Aead::from_key("c2hvcnQ=").unwrap_err().to_string(), "Encryption key is not valid: expected 32 bytes, found 5.");
+    assert_eq!(Aead::from_item(&EncryptionKey::new(None)).unwrap_err().to_string(), "Encryption key item (EncryptionKey) has no value.");
+  }
+
+  #[test]
+  fn decrypted_values_are_secret() {
+    let key = Aead::generate_key();
+    let dir = TempDir::new("encrypted");
+    std::fs::write(dir.join("key"), format!("{}\n", key)).unwrap();
+    let aead = Aead::from_file(dir.join("key")).unwrap();
+
+    let contents = format!("[database]\npassword = \"{}\"\nhost = \"localhost\"\n", aead.encrypt("database.password", "hunter2"));
+    let file = Decrypting::new(ConfigurationFile::from_str("config.toml", &contents, Format::Toml), aead);
+    let strategy = ConfigurationStrategy::new(vec![&file]);
+
+    let mut password = Password::new(None);
+    let res = strategy.try_get(&mut password);
+    assert!(res.is_secret());
+    assert_eq!(res.provenance().unwrap(), "file config.toml key database.password");
+    drop(res);
+    assert_eq!(password.get(), Some(&String::from("hunter2")));
+
+    let mut host = Host::new(None);
+    let res = strategy.try_get(&mut host);
+    assert!(!res.is_secret());
+    drop(res);
+    assert_eq!(host.get(), Some(&String::from("localhost")));
+
+    let other = Aead::from_item(&EncryptionKey::new(Some(Aead::generate_key()))).unwrap();
+    let file = Decrypting::new(ConfigurationFile::from_str("config.toml", &contents, Format::Toml), other);
+    let e = file.try_get(&mut password).unwrap();
+    assert_eq!(e.to_string(), "Value for (Password) could not be decrypted. Encrypted value could not be decrypted; it may have been encrypted with a different key, or for a different item.");
+  }
+
+  #[test]
+  fn values_are_bound_to_their_item() {
+    let aead = Aead::from_key(&Aead::generate_key()).unwrap();
+    let contents = format!("[database]\nhost = \"{}\"\n", aead.encrypt("database.password", "hunter2"));
+    let file = Decrypting::new(ConfigurationFile::from_str("config.toml", &contents, Format::Toml), aead);
+
+    let mut host = Host::new(None);
+    let e = file.try_get(&mut host).unwrap();
+    assert!(matches!(e.downcast_ref::<Error>(), Some(Error::NotDecrypted { .. })));
+    assert_eq!(host.get(), None);
+  }
+
+  #[test]
+  fn rejected_values_are_not_shown() {
+    let aead = Aead::from_key(&Aead::generate_key()).unwrap();
+    let contents = format!("token = \"{}\"\n", aead.encrypt("token", "hunter2"));
+    let file = Decrypting::new(ConfigurationFile::from_str("config.toml", &contents, Format::Toml), aead);
+    let strategy = ConfigurationStrategy::new(vec![&file]);
+
+    let mut token = Token::new(None);
+    let res = strategy.try_get(&mut token);
+    assert_eq!(res.attempts()[0].outcome(), crate::Outcome::Invalid);
+    let invalid = constraint::find_invalid(res.attempts()[0].error().unwrap()).unwrap();
+    assert_eq!(invalid.to_string(), "Value <redacted> is invalid: it must have a length of at most 4.");
+    assert!(!res.report().contains("hunter2"));
+  }
+}
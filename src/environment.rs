@@ -1,7 +1,6 @@
 use std::env;
 use std::ffi::OsString;
-use std::any::Any;
-use std::ops::Deref;
+use std::path::PathBuf;
 
 use convert_case::Casing;
 use convert_case::Case::UpperSnake;
@@ -9,25 +8,135 @@ use derive_new;
 
 use crate::ConfigurationValueSource;
 use crate::ConfigurationItem;
+use crate::constraint;
+use crate::dump;
+use crate::file;
+use crate::value;
+use crate::value::Kind;
+use crate::value::Value;
+
+/// Files named by `<VAR>_FILE` variables larger than this are not read, see
+/// `Environment::with_file_variables`.
+pub const MAX_FILE_SIZE_BYTES: u64 = 64 * 1024;
+
+/// How variables that are set to the empty string are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyValues {
+  /// The empty string is the value, and is passed to the item like any other.
+  Value,
+  /// The item is explicitly unset (see `crate::Unset`), e.g. `APPNAME_PROXY=` overrides a proxy
+  /// set in a configuration file.
+  Unset,
+}
 
 #[derive(Debug, derive_new::new)]
 pub struct Environment {
   prefix: String,
   vars: Vec<(String, Result<String, OsString>)>,
+  // separates the elements of list and map items written in a single variable
+  #[new(value = "value::DELIMITER")]
+  delimiter: char,
+  #[new(value = "EmptyValues::Value")]
+  empty: EmptyValues,
+  // the size limit for files named by `<VAR>_FILE`, if they are honoured
+  #[new(default)]
+  file_variables: Option<u64>,
+  // variables whose names are not valid unicode, and so cannot be looked up
+  #[new(default)]
+  invalid_keys: Vec<OsString>,
 }
 
 impl Environment {
   pub fn from_env(prefix: &str) -> Self {
     let envvars: Vec<_> = env::vars_os().collect();
     let mut vars = Vec::with_capacity(envvars.len());
+    let mut invalid_keys = Vec::new();
 
     envvars.into_iter()
       .for_each(|(k,v)| {
-        match (k.into_string(), v.into_string()) {
-          (Ok(key), res) => vars.push((key, res)),
-          (_, _) => () }});
+        match k.into_string() {
+          Ok(key) => vars.push((key, v.into_string())),
+          Err(key) => invalid_keys.push(key) }});
+
+    let mut env = Self::new(prefix.to_string(), vars);
+    env.invalid_keys = invalid_keys;
+    env
+  }
+
+  /// Return the names of variables that were ignored because they are not valid unicode.
+  pub fn invalid_keys(&self) -> &[OsString] {
+    &self.invalid_keys
+  }
+
+  /// Choose how variables that are set to the empty string are treated.
+  /// Defaults to `EmptyValues::Value`.
+  pub fn with_empty_values(mut self, empty: EmptyValues) -> Self {
+    self.empty = empty;
+    self
+  }
+
+  /// Also read values from files named by `<VAR>_FILE` variables, e.g.
+  /// `APPNAME_DB_PASSWORD_FILE=/run/secrets/db`, as many container images do. Trailing newlines
+  /// are removed, and files larger than `max_file_size_bytes` are not read. Setting both
+  /// `<VAR>` and `<VAR>_FILE` is an error. Values read from files are treated as secret.
+  pub fn with_file_variables(mut self, max_file_size_bytes: u64) -> Self {
+    self.file_variables = Some(max_file_size_bytes);
+    self
+  }
+
+  // the `<VAR>_FILE` variable for `envvar` and the path it names, if it is set and honoured
+  fn file_variable(&self, envvar: &str) -> Option<(String, PathBuf)> {
+    self.file_variables?;
+    let file_envvar = format!("{}_FILE", envvar);
+    let path = match self.lookup(&file_envvar)? {
+      Ok(s) => PathBuf::from(s),
+      Err(s) => PathBuf::from(s),
+    };
+    if path.as_os_str().is_empty() {
+      return None;
+    }
+    Some((file_envvar, path))
+  }
+
+  // read the value from the file named by `file_envvar`
+  fn try_get_from_file(&self, ci: &mut dyn ConfigurationItem, envvar: String, file_envvar: String, path: PathBuf) -> Option<Box<dyn std::error::Error>> {
+    use Error::*;
+    let name = String::from(ci.get_name());
+    if self.lookup(&envvar).is_some() {
+      return Some(Box::new(BothForms { name: name, envvar: envvar, file_envvar: file_envvar }));
+    }
+
+    let f = file::File::new(&path, self.file_variables.unwrap_or(MAX_FILE_SIZE_BYTES));
+    let value = match f.contents().map(std::str::from_utf8) {
+      None => return Some(Box::new(FileNotRead { name: name, file_envvar: file_envvar, path: path, reason: f.result().to_string() })),
+      Some(Err(_)) => return Some(Box::new(FileNotRead { name: name, file_envvar: file_envvar, path: path, reason: String::from("file is not valid unicode") })),
+      Some(Ok(s)) => file::trim_newlines(s),
+    };
+
+    ci.try_value(&Box::new(value.to_string())).map(|e| Box::new(FileValueNotAccepted {
+      name: name,
+      file_envvar: file_envvar,
+      path: path,
+      hint: crate::metadata::hint(ci, e.as_ref()),
+      source: constraint::redact(e.as_ref()),
+    }) as Box<dyn std::error::Error>)
+  }
+
+  /// Return the name of the environment variable this source reads for the item.
+  pub fn envvar_name(&self, ci: &dyn ConfigurationItem) -> String {
+    let ci_name = ci.get_name().to_case(UpperSnake);
+    if let Some(group) = ci.get_group() {
+      format!("{}__{}__{}", self.prefix, group, ci_name)
+    } else {
+      format!("{}_{}", self.prefix, ci_name)
+    }
+  }
 
-    Self::new(prefix.to_string(), vars)
+  /// Use a different delimiter between the elements of list and map items, e.g. `:` for
+  /// `APPNAME_PATH=a:b`. Defaults to `,`.
+  pub fn with_delimiter(mut self, delimiter: char) -> Self {
+    self.delimiter = delimiter;
+    self
   }
 
   pub fn lookup(&self, name: &str) -> Option<&Result<String, OsString>> {
@@ -37,6 +146,64 @@ impl Environment {
 
     None
   }
+
+  /// Collect the value of a list or map item, either from a single variable holding delimited
+  /// values (`APPNAME_HOSTS=a,b`, `APPNAME_LABELS=team=x,env=prod`), or from indexed variables
+  /// (`APPNAME_HOSTS__0=a`, `APPNAME_LABELS__team=x`). Setting both forms is an error.
+  fn lookup_collection(&self, name: &str, envvar: &str, kind: &Kind, secret: bool) -> Option<Result<Value, Error>> {
+    use Error::*;
+    let indexed_prefix = format!("{}__", envvar);
+    let indexed: Vec<(&str, &Result<String, OsString>)> = self.vars.iter()
+      .filter_map(|(k, v)| k.strip_prefix(&indexed_prefix).map(|suffix| (suffix, v)))
+      .collect();
+
+    let not_unicode = |envvar: &str, v: &OsString| NotUnicode {
+      name: name.into(),
+      envvar: envvar.into(),
+      source: constraint::redact_if(secret, <String as value::ConfigurationValue>::from_os_str(v).unwrap_err()),
+    };
+
+    match (self.lookup(envvar), indexed.is_empty()) {
+      (None, true) => None,
+      (Some(_), false) => Some(Err(ConflictingForms { name: name.into(), envvar: envvar.into() })),
+      (Some(Err(v)), true) => Some(Err(not_unicode(envvar, v))),
+      (Some(Ok(s)), true) => Some(match kind {
+        Kind::Map(_) => value::split_map(s, self.delimiter)
+          .map(Value::Map)
+          .map_err(|e| Malformed { name: name.into(), envvar: envvar.into(), source: e }),
+        _ => Ok(Value::List(value::split_list(s, self.delimiter))),
+      }),
+      (None, false) => {
+        let mut entries = Vec::with_capacity(indexed.len());
+        for (suffix, v) in indexed {
+          match v {
+            Ok(s) => entries.push((suffix, Value::String(s.clone()))),
+            Err(v) => return Some(Err(not_unicode(&format!("{}{}", indexed_prefix, suffix), v))),
+          }
+        }
+
+        if let Kind::Map(_) = kind {
+          return Some(Ok(Value::Map(entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect())));
+        }
+
+        let mut elements = Vec::with_capacity(entries.len());
+        for (suffix, v) in entries {
+          match suffix.parse::<usize>() {
+            Ok(i) => elements.push((i, v)),
+            Err(_) => return Some(Err(BadIndex { name: name.into(), envvar: format!("{}{}", indexed_prefix, suffix) })),
+          }
+        }
+        elements.sort_by_key(|(i, _)| *i);
+        for (position, (i, _)) in elements.iter().enumerate() {
+          if position != *i {
+            return Some(Err(MissingIndex { name: name.into(), envvar: format!("{}{}", indexed_prefix, position) }));
+          }
+        }
+
+        Some(Ok(Value::List(elements.into_iter().map(|(_, v)| v).collect())))
+      }
+    }
+  }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -46,11 +213,62 @@ enum Error {
     name: String,
     envvar: String,
   },
-  #[error("Value for environment variable ({name}={value:?}) was not accepted.")]
+  #[error("Both {envvar} and indexed variables ({envvar}__*) are set for ({name}); use only one.")]
+  ConflictingForms {
+    name: String,
+    envvar: String,
+  },
+  #[error("Environment variable ({envvar}) for ({name}) was not accepted: {source}")]
+  NotUnicode {
+    name: String,
+    envvar: String,
+    source: Box<dyn std::error::Error>,
+  },
+  #[error("Environment variable ({envvar}) for ({name}) should end in a number.")]
+  BadIndex {
+    name: String,
+    envvar: String,
+  },
+  #[error("Environment variable ({envvar}) for ({name}) is missing; indexes must start at 0 and have no gaps.")]
+  MissingIndex {
+    name: String,
+    envvar: String,
+  },
+  #[error("Value for environment variable ({envvar}) for ({name}) is malformed.")]
+  Malformed {
+    name: String,
+    envvar: String,
+    source: value::ValueError,
+  },
+  #[error("Both {envvar} and {file_envvar} are set for ({name}); use only one.")]
+  BothForms {
+    name: String,
+    envvar: String,
+    file_envvar: String,
+  },
+  #[error("Could not read file ({path}) named by {file_envvar} for ({name}): {reason}.")]
+  FileNotRead {
+    name: String,
+    file_envvar: String,
+    path: PathBuf,
+    reason: String,
+  },
+  // the value is not shown, as files are mostly used for secrets (see `is_secret`)
+  #[error("Value in file ({path}) named by {file_envvar} was not accepted. {hint}")]
+  FileValueNotAccepted {
+    name: String,
+    file_envvar: String,
+    path: PathBuf,
+    hint: String,
+    source: Box<dyn std::error::Error>,
+  },
+  #[error("Value for environment variable ({envvar}={value}) was not accepted. {hint}")]
   ValueNotAccepted {
     name: String,
     envvar: String,
-    value: Result<String, OsString>,
+    // quoted, with any bytes that are not valid unicode escaped
+    value: String,
+    hint: String,
     source: Box<dyn std::error::Error>,
   },
 }
@@ -58,34 +276,64 @@ enum Error {
 impl ConfigurationValueSource for Environment {
   fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
     let ci_name = String::from(ci.get_name());
+    let envvar = self.envvar_name(ci);
 
-    let envvar = if let Some(group) = ci.get_group() {
-      format!("{}__{}__{}", self.prefix, group, ci_name.to_case(UpperSnake))
-    } else {
-      format!("{}_{}", self.prefix, ci_name.to_case(UpperSnake))
-    };
+    if let Some((file_envvar, path)) = self.file_variable(&envvar) {
+      return self.try_get_from_file(ci, envvar, file_envvar, path);
+    }
+
+    if self.empty == EmptyValues::Unset {
+      if let Some(Ok(s)) = self.lookup(&envvar) {
+        if s.is_empty() {
+          return Some(Box::new(crate::Unset));
+        }
+      }
+    }
 
     use Error::*;
+    let kind = ci.get_kind();
+    if kind.is_collection() {
+      return match self.lookup_collection(&ci_name, &envvar, &kind, ci.is_secret()) {
+        None => Some(Box::new(EnvironmentVariableNotFound {
+          name: ci_name,
+          envvar: envvar,
+        })),
+        Some(Err(e)) => Some(Box::new(e)),
+        Some(Ok(value)) => match ci.try_value(&Box::new(value.clone())) {
+          Some(e) => Some(Box::new(ValueNotAccepted {
+            name: ci_name,
+            envvar: envvar,
+            value: if ci.is_secret() { dump::REDACTED.into() } else { value.to_json_inline() },
+            hint: crate::metadata::hint(ci, e.as_ref()),
+            source: e,
+          })),
+          None => None,
+        },
+      };
+    }
+
     match self.lookup(&envvar) {
       None => Some(Box::new(EnvironmentVariableNotFound {
         name: ci_name,
         envvar: envvar,
       })),
       Some(r) => match r {
-        &Ok(ref s) => match ci.try_value(&Box::new(s.clone())) {
+        Ok(s) => match ci.try_value(&Box::new(s.clone())) {
           Some(e) => Some(Box::new(ValueNotAccepted {
             name: ci_name.clone(),
             envvar: envvar,
-            value: Ok(s.clone()),
+            value: if ci.is_secret() { dump::REDACTED.into() } else { value::quote_os(s.as_ref()) },
+            hint: crate::metadata::hint(ci, e.as_ref()),
             source: e,
           })),
           None => None,
         },
-        &Err(ref s) => match ci.try_value(&Box::new(s.clone())) {
+        Err(s) => match ci.try_value(&Box::new(s.clone())) {
           Some(e) => Some(Box::new(ValueNotAccepted {
             name: ci_name.clone(),
             envvar: envvar,
-            value: Err(s.clone()),
+            value: if ci.is_secret() { dump::REDACTED.into() } else { value::quote_os(s) },
+            hint: crate::metadata::hint(ci, e.as_ref()),
             source: e,
           })),
           None => None,
@@ -93,6 +341,21 @@ impl ConfigurationValueSource for Environment {
       },
     }
   }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    let envvar = self.envvar_name(ci);
+    match self.file_variable(&envvar) {
+      Some((file_envvar, path)) if self.lookup(&envvar).is_none() =>
+        format!("read from file {} named by {}", path.display(), file_envvar),
+      _ => format!("env {}", envvar),
+    }
+  }
+
+  // values read from files are treated as secret, as files are mostly used for secrets
+  fn is_secret(&self, ci: &dyn ConfigurationItem) -> bool {
+    let envvar = self.envvar_name(ci);
+    self.file_variable(&envvar).is_some() && self.lookup(&envvar).is_none()
+  }
 }
 
 pub fn new(prefix: &str) -> Environment {
@@ -103,6 +366,8 @@ pub fn new(prefix: &str) -> Environment {
 mod test {
   use super::*;
 
+  use crate::testing::TempDir;
+
   #[test]
   fn lookup_fails_when_name_is_absent() {
     let env = Environment::new("APPNAME".into(), Vec::new());
@@ -120,9 +385,6 @@ mod test {
   }
 
   use crate::config;
-  use crate::TryIntoDynErr;
-  use std::error::Error;
-  use derive_new::new;
   config!(
     (EnvTestConfigurationItem String)
   );
@@ -149,6 +411,169 @@ mod test {
   //   }
   // }
 
+  config!(
+    (EnvTestHosts Vec<String>),
+    (EnvTestPorts Vec<u16>),
+    (EnvTestLabels std::collections::HashMap<String, String>)
+  );
+
+  #[test]
+  fn lists_from_delimited_and_indexed_variables() {
+    let env = Environment::new("APPNAME".into(), vec![("APPNAME_ENV_TEST_HOSTS".into(), Ok("a:b".into()))])
+      .with_delimiter(':');
+    let mut ci = EnvTestHosts::new(None);
+    assert!(env.try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&vec![String::from("a"), String::from("b")]));
+
+    let env = Environment::new("APPNAME".into(), vec![
+      ("APPNAME_ENV_TEST_HOSTS__1".into(), Ok("b".into())),
+      ("APPNAME_ENV_TEST_HOSTS__0".into(), Ok("a".into())),
+    ]);
+    let mut ci = EnvTestHosts::new(None);
+    assert!(env.try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&vec![String::from("a"), String::from("b")]));
+  }
+
+  #[test]
+  fn list_errors_point_at_the_element() {
+    let env = Environment::new("APPNAME".into(), vec![
+      ("APPNAME_ENV_TEST_PORTS__0".into(), Ok("80".into())),
+      ("APPNAME_ENV_TEST_PORTS__1".into(), Ok("http".into())),
+    ]);
+    let mut ci = EnvTestPorts::new(None);
+    let e = env.try_get(&mut ci).unwrap();
+    assert_eq!(e.source().unwrap().to_string(), "Element 1 was not accepted: invalid digit found in string");
+
+    let env = Environment::new("APPNAME".into(), vec![("APPNAME_ENV_TEST_PORTS__1".into(), Ok("80".into()))]);
+    let e = env.try_get(&mut ci).unwrap();
+    assert_eq!(
+      e.to_string(),
+      "Environment variable (APPNAME_ENV_TEST_PORTS__0) for (EnvTestPorts) is missing; indexes must start at 0 and have no gaps.");
+  }
+
+  #[test]
+  fn maps_from_indexed_variables() {
+    let env = Environment::new("APPNAME".into(), vec![
+      ("APPNAME_ENV_TEST_LABELS__team".into(), Ok("x".into())),
+      ("APPNAME_ENV_TEST_LABELS__env".into(), Ok("prod".into())),
+    ]);
+    let mut ci = EnvTestLabels::new(None);
+    assert!(env.try_get(&mut ci).is_none());
+    assert_eq!(ci.get().unwrap().get("team"), Some(&String::from("x")));
+    assert_eq!(ci.get().unwrap().get("env"), Some(&String::from("prod")));
+  }
+
+  #[test]
+  fn empty_variables_can_unset_the_item() {
+    let vars = vec![("APPNAME_ENV_TEST_CONFIGURATION_ITEM".into(), Ok("".into()))];
+    let mut ci = EnvTestConfigurationItem::new(None);
+    assert!(Environment::new("APPNAME".into(), vars.clone()).try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&String::new()));
+
+    let mut ci = EnvTestConfigurationItem::new(None);
+    let env = Environment::new("APPNAME".into(), vars).with_empty_values(EmptyValues::Unset);
+    assert!(env.try_get(&mut ci).unwrap().is::<crate::Unset>());
+    assert_eq!(ci.get(), None);
+  }
+
+  config!(
+    (EnvTestRaw OsString)
+  );
+
+  #[cfg(unix)]
+  #[test]
+  fn values_that_are_not_unicode() {
+    use std::os::unix::ffi::OsStringExt;
+    let raw = OsString::from_vec(b"caf\xe9".to_vec());
+    let env = Environment::new("APPNAME".into(), vec![
+      ("APPNAME_ENV_TEST_RAW".into(), Err(raw.clone())),
+      ("APPNAME_ENV_TEST_CONFIGURATION_ITEM".into(), Err(raw.clone())),
+    ]);
+
+    let mut ci = EnvTestRaw::new(None);
+    assert!(env.try_get(&mut ci).is_none());
+    assert_eq!(ci.get(), Some(&raw));
+
+    let mut ci = EnvTestConfigurationItem::new(None);
+    let e = env.try_get(&mut ci).unwrap();
+    assert_eq!(e.to_string(), "Value for environment variable (APPNAME_ENV_TEST_CONFIGURATION_ITEM=\"caf\\xE9\") was not accepted. Expected a value of type string.");
+
+    let env = Environment::new("APPNAME".into(), vec![("APPNAME_ENV_TEST_HOSTS__0".into(), Err(raw.clone()))]);
+    let e = env.try_get(&mut EnvTestHosts::new(None)).unwrap();
+    assert_eq!(
+      e.to_string(),
+      "Environment variable (APPNAME_ENV_TEST_HOSTS__0) for (EnvTestHosts) was not accepted: Expected text, but \"caf\\xE9\" is not valid unicode (invalid bytes: \\xE9).");
+  }
+
+  config!(
+    #[group = "db"]
+    #[secret]
+    (Password String),
+    #[group = "db"]
+    #[max_length = 4]
+    (User String)
+  );
+
+  #[test]
+  fn values_can_be_read_from_files() {
+    let dir = TempDir::new("environment");
+    let path = dir.join("password");
+    std::fs::write(&path, "hunter2\n").unwrap();
+    let file_var = || (String::from("APPNAME__db__PASSWORD_FILE"), Ok(path.to_string_lossy().into_owned()));
+
+    let env = Environment::new("APPNAME".into(), vec![file_var()]);
+    let mut ci = Password::new(None);
+    assert!(env.try_get(&mut ci).is_some());
+
+    let env = env.with_file_variables(MAX_FILE_SIZE_BYTES);
+    let strategy = crate::ConfigurationStrategy::new(vec![&env]);
+    let res = strategy.try_get(&mut ci);
+    assert_eq!(res.report(), format!("db.password:\n  read from file {} named by APPNAME__db__PASSWORD_FILE: accepted\n", path.display()));
+    drop(res);
+    assert_eq!(ci.get(), Some(&String::from("hunter2")));
+
+    let env = Environment::new("APPNAME".into(), vec![file_var()]).with_file_variables(4);
+    let e = env.try_get(&mut ci).unwrap();
+    assert_eq!(e.to_string(), format!("Could not read file ({}) named by APPNAME__db__PASSWORD_FILE for (Password): file is too large (8 bytes).", path.display()));
+
+    let env = Environment::new("APPNAME".into(), vec![file_var(), ("APPNAME__db__PASSWORD".into(), Ok("inline".into()))])
+      .with_file_variables(MAX_FILE_SIZE_BYTES);
+    let e = env.try_get(&mut ci).unwrap();
+    assert_eq!(e.to_string(), "Both APPNAME__db__PASSWORD and APPNAME__db__PASSWORD_FILE are set for (Password); use only one.");
+    assert_eq!(env.describe(&ci), "env APPNAME__db__PASSWORD");
+
+    let env = Environment::new("APPNAME".into(), vec![(String::from("APPNAME__db__USER_FILE"), Ok(path.to_string_lossy().into_owned()))])
+      .with_file_variables(MAX_FILE_SIZE_BYTES);
+    let mut user = User::new(None);
+    assert!(env.is_secret(&user));
+    let e = env.try_get(&mut user).unwrap();
+    assert_eq!(e.source().unwrap().to_string(), "Value <redacted> is invalid: it must have a length of at most 4.");
+  }
+
+  config!(
+    #[secret]
+    #[min_length = 8]
+    (EnvTestToken String),
+
+    #[secret]
+    (EnvTestPin u16)
+  );
+
+  #[test]
+  fn rejected_secrets_are_not_shown() {
+    let env = Environment::new("APPNAME".into(), vec![
+      ("APPNAME_ENV_TEST_TOKEN".into(), Ok("hunter2".into())),
+      ("APPNAME_ENV_TEST_PIN".into(), Ok("hunter2".into())),
+    ]);
+
+    let e = env.try_get(&mut EnvTestToken::new(None)).unwrap();
+    assert_eq!(e.to_string(), "Value for environment variable (APPNAME_ENV_TEST_TOKEN=<redacted>) was not accepted. It must have a length of at least 8.");
+    assert_eq!(e.source().unwrap().to_string(), "Value <redacted> is invalid: it must have a length of at least 8.");
+
+    let e = env.try_get(&mut EnvTestPin::new(None)).unwrap();
+    assert!(!format!("{} {}", e, e.source().unwrap()).contains("hunter2"));
+  }
+
   #[test]
   fn try_get() {
     let env = Environment::new("APPNAME".into(), vec![("APPNAME_ENV_TEST_CONFIGURATION_ITEM".into(), Ok("test_value".into()))]);
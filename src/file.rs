@@ -77,6 +77,48 @@ impl File {
   }
 }
 
+impl File {
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn max_file_size_bytes(&self) -> u64 {
+    self.max_file_size_bytes
+  }
+
+  pub fn result(&self) -> &Result {
+    &self.result
+  }
+
+  /// Return the contents of the file, if it was read successfully.
+  pub fn contents(&self) -> Option<&[u8]> {
+    match &self.result {
+      Result::Success { buf, .. } => Some(buf),
+      _ => None,
+    }
+  }
+}
+
+impl std::fmt::Display for Result {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    use self::Result::*;
+    match self {
+      ErrorAttemptingToOpenFile { err } => write!(f, "could not open file: {}", err),
+      ErrorAttemptingToReadFileMetadata { err } => write!(f, "could not read file metadata: {}", err),
+      PathRefersToADirectory { .. } => write!(f, "path refers to a directory"),
+      FileIsTooLarge { file_size_bytes, .. } => write!(f, "file is too large ({} bytes)", file_size_bytes),
+      ErrorAttemptingToReadFileContents { err, .. } => write!(f, "could not read file contents: {}", err),
+      Success { num_bytes_read, .. } => write!(f, "read {} bytes", num_bytes_read),
+    }
+  }
+}
+
+/// Remove trailing "\n" and "\r\n" from the contents of a file holding a single value, as
+/// written by editors and `echo`.
+pub fn trim_newlines(s: &str) -> &str {
+  s.trim_end_matches(['\n', '\r'])
+}
+
 pub fn new<P: AsRef<Path>>(path: P) -> File {
   File::new(path, 4 * 1024)
 }
@@ -0,0 +1,338 @@
+//! Configuration split across several files: a `conf.d` directory of fragments, and files that
+//! include other files.
+//!
+//! Fragments in a directory are read in lexical order of their names, and later fragments take
+//! precedence, so `/etc/myapp/conf.d/90-local.toml` overrides `10-defaults.toml`.
+//!
+//! A file may include others with a top-level `include` key, holding a path or a list of paths,
+//! relative to the including file. Paths may be glob patterns, e.g. `include = ["extra/*.toml"]`,
+//! whose matches are read in lexical order. The including file takes precedence over the files it
+//! includes, and later includes over earlier ones. Including a file that is already being
+//! included is an error.
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::config_file::ConfigurationFile;
+use crate::config_file::Format;
+use crate::path;
+use crate::schema;
+use crate::value::Value;
+use crate::ConfigurationItem;
+use crate::ConfigurationValueSource;
+
+/// The key holding the files to include.
+pub const INCLUDE_KEY: &str = "include";
+
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum Error {
+  #[error("Could not read configuration directory ({path}): {reason}.")]
+  CouldNotReadDirectory {
+    path: PathBuf,
+    reason: String,
+  },
+  #[error("Include in configuration file ({path}) must be a path or a list of paths, found a {found}.")]
+  NotAPath {
+    path: PathBuf,
+    found: &'static str,
+  },
+  #[error("Include pattern ({pattern}) in configuration file ({path}) is not valid: {reason}.")]
+  InvalidPattern {
+    path: PathBuf,
+    pattern: String,
+    reason: String,
+  },
+  #[error("Included file ({included}) in configuration file ({path}) does not exist.")]
+  IncludeNotFound {
+    path: PathBuf,
+    included: PathBuf,
+  },
+  #[error("Configuration files include each other: {}.", .chain.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(" includes "))]
+  IncludeCycle {
+    chain: Vec<PathBuf>,
+  },
+  #[error("Key ({key}) not found in any configuration file in {description}.")]
+  KeyNotFound {
+    name: String,
+    key: String,
+    description: String,
+  },
+}
+
+/// A source reading several configuration files, consulted highest priority first.
+/// Values are described as coming from the exact file that supplied them.
+#[derive(Debug)]
+pub struct Fragments {
+  description: String,
+  files: Vec<ConfigurationFile>,
+  error: Option<Error>,
+}
+
+impl Fragments {
+  /// Load the configuration files in `dir` (`.toml`, `.json`, `.yaml` and `.yml`), and the files
+  /// they include. Other files are ignored, and a missing directory has no fragments.
+  pub fn directory<P: AsRef<Path>>(dir: P) -> Self {
+    let dir = dir.as_ref();
+    let mut fragments = Self {
+      description: format!("directory {}", dir.display()),
+      files: Vec::new(),
+      error: None,
+    };
+
+    let mut paths = match fs::read_dir(dir) {
+      Ok(entries) => entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && Format::from_path(p).is_some())
+        .collect::<Vec<_>>(),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+      Err(e) => {
+        fragments.error = Some(Error::CouldNotReadDirectory { path: dir.to_path_buf(), reason: e.to_string() });
+        return fragments;
+      }
+    };
+    paths.sort();
+
+    for path in paths.iter().rev() {
+      if let Err(e) = fragments.load(path, &mut Vec::new()) {
+        fragments.error = Some(e);
+        break;
+      }
+    }
+    fragments
+  }
+
+  /// Load the configuration file at `path`, and the files it includes.
+  pub fn file<P: AsRef<Path>>(path: P) -> Self {
+    let path = path.as_ref();
+    let mut fragments = Self {
+      description: format!("file {} and its includes", path.display()),
+      files: Vec::new(),
+      error: None,
+    };
+    if let Err(e) = fragments.load(path, &mut Vec::new()) {
+      fragments.error = Some(e);
+    }
+    fragments
+  }
+
+  // add the file, then the files it includes, latest first; `stack` holds the files including it
+  fn load(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&canonical) {
+      let mut chain = stack.clone();
+      chain.push(canonical);
+      return Err(Error::IncludeCycle { chain: chain });
+    }
+
+    let file = ConfigurationFile::new(path);
+    let included = includes(&file)?;
+    self.files.push(file);
+
+    stack.push(canonical);
+    for p in included.iter().rev() {
+      self.load(p, stack)?;
+    }
+    stack.pop();
+    Ok(())
+  }
+
+  /// Return the paths of the files that were loaded, highest priority first.
+  pub fn files(&self) -> Vec<&Path> {
+    self.files.iter().map(|f| f.path()).collect()
+  }
+
+  /// Return why the files could not all be loaded, if they could not.
+  pub fn error(&self) -> Option<&Error> {
+    self.error.as_ref()
+  }
+}
+
+// the files included by `file`, with glob patterns expanded
+fn includes(file: &ConfigurationFile) -> Result<Vec<PathBuf>, Error> {
+  let patterns = match file.get(INCLUDE_KEY) {
+    None => return Ok(Vec::new()),
+    Some(Value::String(s)) => vec![s.as_str()],
+    Some(Value::List(items)) => items.iter()
+      .map(|v| match v {
+        Value::String(s) => Ok(s.as_str()),
+        v => Err(Error::NotAPath { path: file.path().to_path_buf(), found: v.kind_name() }),
+      })
+      .collect::<Result<Vec<_>, _>>()?,
+    Some(v) => return Err(Error::NotAPath { path: file.path().to_path_buf(), found: v.kind_name() }),
+  };
+
+  let base = file.path().parent().unwrap_or_else(|| Path::new(""));
+  let mut paths = Vec::new();
+  for pattern in patterns {
+    let full = base.join(path::expand_home(pattern));
+    if !pattern.contains(['*', '?', '[']) {
+      if !full.is_file() {
+        return Err(Error::IncludeNotFound { path: file.path().to_path_buf(), included: full });
+      }
+      paths.push(full);
+      continue;
+    }
+
+    let invalid = |reason: String| Error::InvalidPattern {
+      path: file.path().to_path_buf(),
+      pattern: pattern.into(),
+      reason: reason,
+    };
+    // only the included pattern is a pattern: the directory of the file may contain `*`, `?` or `[`
+    let expanded = path::expand_home(pattern);
+    let full = if expanded.is_absolute() { expanded } else {
+      Path::new(&glob::Pattern::escape(&base.to_string_lossy())).join(expanded)
+    };
+    let matches = glob::glob(&full.to_string_lossy()).map_err(|e| invalid(e.to_string()))?;
+    for m in matches {
+      let m = m.map_err(|e| invalid(e.to_string()))?;
+      if m.is_file() {
+        paths.push(m);
+      }
+    }
+  }
+  Ok(paths)
+}
+
+impl ConfigurationValueSource for Fragments {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    if let Some(e) = &self.error {
+      return Some(Box::new(e.clone()));
+    }
+
+    for file in self.files.iter() {
+      if let Some(e) = file.load_error() {
+        return Some(Box::new(e.clone()));
+      }
+      if file.contains(ci) {
+        return file.try_get(ci);
+      }
+    }
+
+    Some(Box::new(Error::KeyNotFound {
+      name: ci.get_name().into(),
+      key: schema::file_key(ci),
+      description: self.description.clone(),
+    }))
+  }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    match self.files.iter().find(|f| f.contains(ci)) {
+      Some(file) => file.describe(ci),
+      None => format!("{} key {}", self.description, schema::file_key(ci)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+  use crate::ConfigurationStrategy;
+  use crate::testing::TempDir;
+
+  config!(
+    (Port u16),
+    (Workers u32),
+    #[group = "database"]
+    (Host String),
+    (Missing String)
+  );
+
+  #[test]
+  fn later_fragments_take_precedence() {
+    let dir = TempDir::new("fragments-conf.d");
+    std::fs::write(dir.join("10-defaults.toml"), "port = 80\nworkers = 4\n[database]\nhost = \"localhost\"\n").unwrap();
+    std::fs::write(dir.join("20-site.yaml"), "workers: 8\n").unwrap();
+    std::fs::write(dir.join("90-local.toml"), "port = 8080\n").unwrap();
+    std::fs::write(dir.join("README"), "not configuration").unwrap();
+
+    let fragments = Fragments::directory(&dir);
+    assert_eq!(fragments.files(), vec![dir.join("90-local.toml"), dir.join("20-site.yaml"), dir.join("10-defaults.toml")]);
+
+    let strategy = ConfigurationStrategy::new(vec![&fragments]);
+    let mut port = Port::new(None);
+    let res = strategy.try_get(&mut port);
+    assert_eq!(res.provenance().unwrap(), format!("file {} key port", dir.join("90-local.toml").display()));
+    drop(res);
+    assert_eq!(port.get(), Some(&8080));
+
+    let mut workers = Workers::new(None);
+    let res = strategy.try_get(&mut workers);
+    assert_eq!(res.provenance().unwrap(), format!("file {} key workers", dir.join("20-site.yaml").display()));
+    drop(res);
+    assert_eq!(workers.get(), Some(&8));
+
+    let mut host = Host::new(None);
+    assert!(fragments.try_get(&mut host).is_none());
+    assert_eq!(fragments.describe(&host), format!("file {} key database.host", dir.join("10-defaults.toml").display()));
+
+    let mut missing = Missing::new(None);
+    let e = fragments.try_get(&mut missing).unwrap();
+    assert_eq!(e.to_string(), format!("Key (missing) not found in any configuration file in directory {}.", dir.display()));
+  }
+
+  #[test]
+  fn directories_are_not_patterns() {
+    let dir = TempDir::new("fragments-[x]");
+    std::fs::write(dir.join("config.toml"), "include = \"*.yaml\"\n").unwrap();
+    std::fs::write(dir.join("site.yaml"), "port: 8080\n").unwrap();
+
+    let fragments = Fragments::file(dir.join("config.toml"));
+    assert!(fragments.error().is_none());
+    assert_eq!(fragments.files(), vec![dir.join("config.toml"), dir.join("site.yaml")]);
+  }
+
+  #[test]
+  fn includes_are_expanded() {
+    let dir = TempDir::new("fragments-include");
+    std::fs::create_dir_all(dir.join("extra")).unwrap();
+    std::fs::write(dir.join("config.toml"), "include = [\"base.toml\", \"extra/*.toml\"]\nport = 443\n").unwrap();
+    std::fs::write(dir.join("base.toml"), "port = 80\nworkers = 2\n[database]\nhost = \"base\"\n").unwrap();
+    std::fs::write(dir.join("extra/a.toml"), "[database]\nhost = \"a\"\n").unwrap();
+    std::fs::write(dir.join("extra/b.toml"), "[database]\nhost = \"b\"\n").unwrap();
+
+    let fragments = Fragments::file(dir.join("config.toml"));
+    assert!(fragments.error().is_none());
+    assert_eq!(fragments.files(), vec![dir.join("config.toml"), dir.join("extra/b.toml"), dir.join("extra/a.toml"), dir.join("base.toml")]);
+
+    let mut port = Port::new(None);
+    assert!(fragments.try_get(&mut port).is_none());
+    assert_eq!(port.get(), Some(&443));
+
+    let mut host = Host::new(None);
+    assert!(fragments.try_get(&mut host).is_none());
+    assert_eq!(host.get(), Some(&String::from("b")));
+    assert_eq!(fragments.describe(&host), format!("file {} key database.host", dir.join("extra/b.toml").display()));
+
+    let mut workers = Workers::new(None);
+    assert!(fragments.try_get(&mut workers).is_none());
+    assert_eq!(fragments.describe(&workers), format!("file {} key workers", dir.join("base.toml").display()));
+  }
+
+  #[test]
+  fn include_cycles_are_errors() {
+    let dir = TempDir::new("fragments-cycle");
+    std::fs::write(dir.join("a.toml"), "include = \"b.toml\"\nport = 1\n").unwrap();
+    std::fs::write(dir.join("b.toml"), "include = \"a.toml\"\n").unwrap();
+    let dir = std::fs::canonicalize(&dir).unwrap();
+
+    let fragments = Fragments::file(dir.join("a.toml"));
+    let mut port = Port::new(None);
+    let e = fragments.try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), format!(
+      "Configuration files include each other: {} includes {} includes {}.",
+      dir.join("a.toml").display(), dir.join("b.toml").display(), dir.join("a.toml").display(),
+    ));
+
+    std::fs::write(dir.join("b.toml"), "include = \"c.toml\"\n").unwrap();
+    let e = Fragments::file(dir.join("a.toml")).try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), format!(
+      "Included file ({}) in configuration file ({}) does not exist.",
+      dir.join("c.toml").display(), dir.join("b.toml").display(),
+    ));
+  }
+}
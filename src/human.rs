@@ -0,0 +1,410 @@
+//! Parsers for human-friendly values: durations ("1h30m"), byte sizes ("10MiB"), percentages
+//! ("50%") and lenient booleans ("yes", "off").
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::time::Duration;
+
+use crate::value::ConfigurationValue;
+use crate::value::Kind;
+use crate::value::Value;
+
+/// Describes why a human-friendly value could not be parsed.
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ParseError {
+  #[error("Empty string is not a valid {what}.")]
+  Empty {
+    what: &'static str,
+  },
+  #[error("Expected a number at position {position} of {input:?}.")]
+  ExpectedNumber {
+    input: String,
+    position: usize,
+  },
+  #[error("Missing unit after {number:?} in {input:?}; expected one of {expected}.")]
+  MissingUnit {
+    input: String,
+    number: String,
+    expected: &'static str,
+  },
+  #[error("Unknown unit {unit:?} in {input:?}; expected one of {expected}.")]
+  UnknownUnit {
+    input: String,
+    unit: String,
+    expected: &'static str,
+  },
+  #[error("Value {input:?} is too large.")]
+  Overflow {
+    input: String,
+  },
+  #[error("Value {input:?} is not a whole number of bytes.")]
+  FractionalBytes {
+    input: String,
+  },
+  #[error("Value {input:?} is not a valid boolean; expected one of true/false, yes/no, on/off, 1/0.")]
+  NotABoolean {
+    input: String,
+  },
+  #[error("Value {input:?} is not a valid percentage; expected e.g. \"50%\" or \"0.5\".")]
+  NotAPercentage {
+    input: String,
+  },
+}
+
+const DURATION_UNITS: &str = "ns, us, ms, s, m, h, d";
+const BYTE_SIZE_UNITS: &str = "B, k, KB, KiB, m, MB, MiB, g, GB, GiB, t, TB, TiB";
+
+/// Split the input into (number, unit) pairs, e.g. "1h30m" into [("1", "h"), ("30", "m")].
+fn components(input: &str) -> Result<Vec<(&str, &str)>, ParseError> {
+  let mut components = Vec::new();
+  let mut rest = input.trim_start();
+
+  while !rest.is_empty() {
+    let position = input.len() - rest.len();
+    let number_len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+    if number_len == 0 {
+      return Err(ParseError::ExpectedNumber { input: input.into(), position: position });
+    }
+    let (number, tail) = rest.split_at(number_len);
+    let tail = tail.trim_start();
+    let unit_len = tail.find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace()).unwrap_or(tail.len());
+    let (unit, tail) = tail.split_at(unit_len);
+    components.push((number, unit));
+    rest = tail.trim_start();
+  }
+
+  Ok(components)
+}
+
+// `number` times `unit`, computed exactly and rounded to the nearest whole number; also returns
+// whether no rounding was needed
+fn scale(input: &str, number: &str, unit: u128) -> Result<(u128, bool), ParseError> {
+  let expected = || ParseError::ExpectedNumber { input: input.into(), position: input.find(number).unwrap_or(0) };
+  let overflow = || ParseError::Overflow { input: input.into() };
+
+  let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+  if (whole.is_empty() && fraction.is_empty()) || fraction.contains('.') {
+    return Err(expected());
+  }
+  let whole = if whole.is_empty() { 0 } else { whole.parse::<u128>().map_err(|_| overflow())? };
+
+  // further digits are far smaller than a nanosecond or a byte, but still make the value inexact
+  let (fraction, rest) = fraction.split_at(fraction.len().min(20));
+  let denominator = 10u128.pow(fraction.len() as u32);
+  let numerator = if fraction.is_empty() { 0 } else { fraction.parse::<u128>().map_err(|_| expected())? } * unit;
+
+  let total = whole.checked_mul(unit)
+    .and_then(|n| n.checked_add((numerator + denominator / 2) / denominator))
+    .ok_or_else(overflow)?;
+  Ok((total, numerator.is_multiple_of(denominator) && rest.chars().all(|c| c == '0')))
+}
+
+/// Parse a duration such as "30s", "1h30m" or "250ms".
+/// Every number must have a unit: ns, us (or µs), ms, s, m, h or d.
+/// Fractions of a nanosecond are rounded.
+pub fn parse_duration(input: &str) -> Result<Duration, ParseError> {
+  if input.trim().is_empty() {
+    return Err(ParseError::Empty { what: "duration" });
+  }
+
+  let mut total_nanos = 0u128;
+  for (n, unit) in components(input)? {
+    let nanos: u128 = match unit {
+      "ns" => 1,
+      "us" | "µs" => 1_000,
+      "ms" => 1_000_000,
+      "s" => 1_000_000_000,
+      "m" => 60_000_000_000,
+      "h" => 3_600_000_000_000,
+      "d" => 86_400_000_000_000,
+      "" => return Err(ParseError::MissingUnit { input: input.into(), number: n.to_string(), expected: DURATION_UNITS }),
+      other => return Err(ParseError::UnknownUnit { input: input.into(), unit: other.into(), expected: DURATION_UNITS }),
+    };
+    let (n, _) = scale(input, n, nanos)?;
+    total_nanos = total_nanos.checked_add(n).ok_or_else(|| ParseError::Overflow { input: input.into() })?;
+  }
+
+  let secs = u64::try_from(total_nanos / 1_000_000_000).map_err(|_| ParseError::Overflow { input: input.into() })?;
+  Ok(Duration::new(secs, (total_nanos % 1_000_000_000) as u32))
+}
+
+/// Format a duration the way `parse_duration` accepts it, e.g. "1h30m" or "250ms".
+pub fn format_duration(duration: &Duration) -> String {
+  let mut secs = duration.as_secs();
+  let nanos = duration.subsec_nanos();
+  let mut out = String::new();
+
+  for (unit, size) in [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)].iter() {
+    if secs >= *size {
+      out.push_str(&format!("{}{}", secs / size, unit));
+      secs %= size;
+    }
+  }
+
+  if nanos > 0 {
+    if nanos.is_multiple_of(1_000_000) { out.push_str(&format!("{}ms", nanos / 1_000_000)); }
+    else if nanos.is_multiple_of(1_000) { out.push_str(&format!("{}us", nanos / 1_000)); }
+    else { out.push_str(&format!("{}ns", nanos)); }
+  }
+
+  if out.is_empty() { String::from("0s") } else { out }
+}
+
+impl ConfigurationValue for Duration {
+  fn kind() -> Kind { Kind::Duration }
+  fn to_value(&self) -> Value { Value::String(format_duration(self)) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Ok(parse_duration(s)?) }
+
+  // a number in a configuration file, e.g. `timeout = 30`, is a number of seconds
+  fn from_value(value: &Value) -> Result<Self, Box<dyn Error>> {
+    match value {
+      Value::Integer(_) | Value::Number(_) => Self::parse(&format!("{}s", value.as_scalar_string().unwrap_or_default())),
+      other => other.as_scalar_string()
+        .ok_or_else(|| Box::new(crate::value::ValueError::ExpectedSingleValue { found: other.kind_name() }) as Box<dyn Error>)
+        .and_then(|s| Self::parse(&s)),
+    }
+  }
+}
+
+impl crate::value::Literal<Duration> for &str {
+  /// Panics if the literal is not a valid duration, since it was written by the programmer.
+  fn convert(self) -> Duration {
+    parse_duration(self).unwrap_or_else(|e| panic!("invalid duration literal: {}", e))
+  }
+}
+
+/// A number of bytes, parsed from e.g. "10MiB" or "4k".
+///
+/// Suffixes ending in "iB" and single letters (k, m, g, t) are powers of 1024;
+/// the SI suffixes (KB, MB, GB, TB) are powers of 1000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+/// Parse a byte size such as "10MiB", "4k", "1.5KiB" or "512".
+/// Fractions must come to a whole number of bytes.
+pub fn parse_byte_size(input: &str) -> Result<ByteSize, ParseError> {
+  let components = components(input)?;
+  let (n, unit) = match components.as_slice() {
+    [] => return Err(ParseError::Empty { what: "byte size" }),
+    [(n, unit)] => (*n, *unit),
+    [_, (n, _), ..] => return Err(ParseError::ExpectedNumber {
+      input: input.into(),
+      position: input.find(n).unwrap_or(0) + n.len(),
+    }),
+  };
+
+  let multiplier: u128 = match unit {
+    "" | "B" | "b" => 1,
+    "k" | "K" | "KiB" | "kiB" => 1 << 10,
+    "m" | "M" | "MiB" => 1 << 20,
+    "g" | "G" | "GiB" => 1 << 30,
+    "t" | "T" | "TiB" => 1 << 40,
+    "kB" | "KB" => 1_000,
+    "MB" => 1_000_000,
+    "GB" => 1_000_000_000,
+    "TB" => 1_000_000_000_000,
+    other => return Err(ParseError::UnknownUnit { input: input.into(), unit: other.into(), expected: BYTE_SIZE_UNITS }),
+  };
+
+  match scale(input, n, multiplier)? {
+    (_, false) => Err(ParseError::FractionalBytes { input: input.into() }),
+    (bytes, true) => u64::try_from(bytes).map(ByteSize).map_err(|_| ParseError::Overflow { input: input.into() }),
+  }
+}
+
+impl std::fmt::Display for ByteSize {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    for (unit, size) in [("TiB", 1u64 << 40), ("GiB", 1 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10)].iter() {
+      if self.0 >= *size && self.0.is_multiple_of(*size) {
+        return write!(f, "{}{}", self.0 / size, unit);
+      }
+    }
+    write!(f, "{}B", self.0)
+  }
+}
+
+impl ConfigurationValue for ByteSize {
+  fn kind() -> Kind { Kind::ByteSize }
+  fn to_value(&self) -> Value { Value::String(self.to_string()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Ok(parse_byte_size(s)?) }
+}
+
+impl crate::value::Literal<ByteSize> for &str {
+  /// Panics if the literal is not a valid byte size, since it was written by the programmer.
+  fn convert(self) -> ByteSize {
+    parse_byte_size(self).unwrap_or_else(|e| panic!("invalid byte size literal: {}", e))
+  }
+}
+
+/// A fraction, parsed from e.g. "50%" or "0.5".
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Percentage(pub f64);
+
+/// Parse a percentage such as "50%" (which is 0.5), or a plain fraction such as "0.5".
+pub fn parse_percentage(input: &str) -> Result<Percentage, ParseError> {
+  let trimmed = input.trim();
+  let (number, scale) = match trimmed.strip_suffix('%') {
+    Some(n) => (n.trim_end(), 100.0),
+    None => (trimmed, 1.0),
+  };
+
+  match number.parse::<f64>() {
+    Ok(n) if n.is_finite() => Ok(Percentage(n / scale)),
+    _ => Err(ParseError::NotAPercentage { input: input.into() }),
+  }
+}
+
+impl std::fmt::Display for Percentage {
+  /// Written with at most 10 decimal places, so that 0.07 is "7%" rather than "7.000000000000001%".
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let fixed = format!("{:.10}", self.0 * 100.0);
+    let trimmed = fixed.trim_end_matches('0').trim_end_matches('.');
+    write!(f, "{}%", if trimmed == "-0" { "0" } else { trimmed })
+  }
+}
+
+impl ConfigurationValue for Percentage {
+  fn kind() -> Kind { Kind::Percentage }
+  fn to_value(&self) -> Value { Value::String(self.to_string()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Ok(parse_percentage(s)?) }
+}
+
+impl crate::value::Literal<Percentage> for &str {
+  /// Panics if the literal is not a valid percentage, since it was written by the programmer.
+  fn convert(self) -> Percentage {
+    parse_percentage(self).unwrap_or_else(|e| panic!("invalid percentage literal: {}", e))
+  }
+}
+
+/// Parse a boolean leniently: true/false, yes/no, on/off, y/n and 1/0, ignoring case.
+pub fn parse_bool(input: &str) -> Result<bool, ParseError> {
+  match input.trim().to_ascii_lowercase().as_str() {
+    "true" | "yes" | "y" | "on" | "1" => Ok(true),
+    "false" | "no" | "n" | "off" | "0" => Ok(false),
+    _ => Err(ParseError::NotABoolean { input: input.into() }),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn durations() {
+    assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+    assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+    assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("1h 30m"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("1ms"), Ok(Duration::from_millis(1)));
+    assert_eq!(parse_duration("18446744073709551615s"), Ok(Duration::from_secs(u64::MAX)));
+    assert_eq!(parse_duration("9007199254740993ns"), Ok(Duration::from_nanos(9_007_199_254_740_993)));
+    assert_eq!(parse_duration("0.1s"), Ok(Duration::from_millis(100)));
+    assert_eq!(parse_duration("1.0000000005s"), Ok(Duration::new(1, 1)));
+
+    assert_eq!(format_duration(&Duration::from_secs(5400)), "1h30m");
+    assert_eq!(format_duration(&Duration::from_millis(250)), "250ms");
+    assert_eq!(format_duration(&Duration::from_secs(0)), "0s");
+  }
+
+  #[test]
+  fn duration_errors() {
+    assert_eq!(
+      parse_duration("30").unwrap_err().to_string(),
+      "Missing unit after \"30\" in \"30\"; expected one of ns, us, ms, s, m, h, d.");
+    assert_eq!(
+      parse_duration("30x").unwrap_err().to_string(),
+      "Unknown unit \"x\" in \"30x\"; expected one of ns, us, ms, s, m, h, d.");
+    assert_eq!(
+      parse_duration("h").unwrap_err(),
+      ParseError::ExpectedNumber { input: "h".into(), position: 0 });
+    assert_eq!(
+      parse_duration("1.2.3s").unwrap_err(),
+      ParseError::ExpectedNumber { input: "1.2.3s".into(), position: 0 });
+    assert_eq!(
+      parse_duration("18446744073709551616s").unwrap_err(),
+      ParseError::Overflow { input: "18446744073709551616s".into() });
+  }
+
+  use crate::config;
+  use crate::ConfigurationItem;
+  use crate::ConfigurationValueSource;
+  use crate::environment::Environment;
+
+  config!(
+    #[default = "30s"]
+    (RequestTimeout Duration),
+    (BufferSize ByteSize),
+    #[max = "1h"]
+    (IdleTimeout Duration)
+  );
+
+  #[test]
+  fn bounds_compare_durations() {
+    let mut idle = IdleTimeout::new(None);
+    assert!(idle.try_value(&Box::new(String::from("30s"))).is_none());
+    let e = idle.try_value(&Box::new(String::from("90m"))).unwrap();
+    assert_eq!(e.to_string(), "Value \"1h30m\" is invalid: it must be at most \"1h\".");
+  }
+
+  #[test]
+  fn items_parse_from_environment() {
+    let env = Environment::new("APPNAME".into(), vec![
+      ("APPNAME_REQUEST_TIMEOUT".into(), Ok("1h30m".into())),
+      ("APPNAME_BUFFER_SIZE".into(), Ok("10MiB".into())),
+    ]);
+
+    let mut timeout = RequestTimeout::new(None);
+    assert!(env.try_get(&mut timeout).is_none());
+    assert_eq!(timeout.get(), Some(&Duration::from_secs(5400)));
+    assert_eq!(RequestTimeout::default_value(), Some(Duration::from_secs(30)));
+
+    let mut size = BufferSize::new(None);
+    assert!(env.try_get(&mut size).is_none());
+    assert_eq!(size.get(), Some(&ByteSize(10 * 1024 * 1024)));
+
+    // numbers in files are seconds
+    assert!(timeout.try_value(&Box::new(Value::Integer(90))).is_none());
+    assert_eq!(timeout.get(), Some(&Duration::from_secs(90)));
+    assert!(timeout.try_value(&Box::new(Value::Number(0.5))).is_none());
+    assert_eq!(timeout.get(), Some(&Duration::from_millis(500)));
+  }
+
+  #[test]
+  fn byte_sizes() {
+    assert_eq!(parse_byte_size("10MiB"), Ok(ByteSize(10 * 1024 * 1024)));
+    assert_eq!(parse_byte_size("4k"), Ok(ByteSize(4096)));
+    assert_eq!(parse_byte_size("4kB"), Ok(ByteSize(4000)));
+    assert_eq!(parse_byte_size("512"), Ok(ByteSize(512)));
+    assert_eq!(parse_byte_size("1.5KiB"), Ok(ByteSize(1536)));
+    assert_eq!(parse_byte_size("18446744073709551615"), Ok(ByteSize(u64::MAX)));
+    assert_eq!(
+      parse_byte_size("1.3KiB").unwrap_err().to_string(),
+      "Value \"1.3KiB\" is not a whole number of bytes.");
+    assert_eq!(
+      parse_byte_size("16EiB").unwrap_err(),
+      ParseError::UnknownUnit { input: "16EiB".into(), unit: "EiB".into(), expected: BYTE_SIZE_UNITS });
+    assert_eq!(
+      parse_byte_size("16777216TiB").unwrap_err(),
+      ParseError::Overflow { input: "16777216TiB".into() });
+    assert_eq!(ByteSize(10 * 1024 * 1024).to_string(), "10MiB");
+    assert_eq!(
+      parse_byte_size("4 parsecs").unwrap_err().to_string(),
+      "Unknown unit \"parsecs\" in \"4 parsecs\"; expected one of B, k, KB, KiB, m, MB, MiB, g, GB, GiB, t, TB, TiB.");
+  }
+
+  #[test]
+  fn percentages_and_booleans() {
+    assert_eq!(parse_percentage("50%"), Ok(Percentage(0.5)));
+    assert_eq!(parse_percentage("0.25"), Ok(Percentage(0.25)));
+    assert!(parse_percentage("half").is_err());
+    for p in &["7%", "12.5%", "0.1%", "33.3%", "100%", "0%", "250%"] {
+      assert_eq!(parse_percentage(p).unwrap().to_string(), *p);
+    }
+    assert_eq!(Percentage(0.07).to_string(), "7%");
+
+    for t in &["yes", "ON", "1", "True", "y"] { assert_eq!(parse_bool(t), Ok(true)); }
+    for f in &["no", "Off", "0", "FALSE", "n"] { assert_eq!(parse_bool(f), Ok(false)); }
+    assert!(parse_bool("maybe").is_err());
+  }
+}
@@ -0,0 +1,503 @@
+//! Source of configuration values fetched from a key-value service over HTTP, like the HTTP APIs
+//! of Consul or etcd.
+//!
+//! Each item is either fetched on its own, with `GET <base url>/<group>/<name>` returning the
+//! value as text, or looked up in a JSON object fetched once for a whole prefix, with
+//! `GET <base url>/<prefix>` returning e.g. `{"port": 8080, "database": {"host": "db"}}`.
+//!
+//! Responses can be kept in a cache directory, which is used instead when the service cannot be
+//! reached, times out or fails with a server error. Values of secret items are not written to the
+//! cache: for a prefix, the cache holds only the values of the other items looked up so far.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config_file::Format;
+use crate::dump;
+use crate::file;
+use crate::schema;
+use crate::value::Value;
+use crate::ConfigurationItem;
+use crate::ConfigurationValueSource;
+
+/// Requests that take longer than this fail, unless another timeout is specified.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Responses larger than this are not accepted.
+pub const MAX_RESPONSE_BYTES: u64 = 1024 * 1024;
+
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum Error {
+  #[error("Could not fetch ({url}) for ({name}): {reason}{}.", .cache.as_ref().map(|p| format!("; no cached copy in ({})", p.display())).unwrap_or_default())]
+  CouldNotFetch {
+    name: String,
+    url: String,
+    reason: String,
+    // where a cached copy was looked for
+    cache: Option<PathBuf>,
+  },
+  #[error("Key for ({name}) not found at ({url}).")]
+  KeyNotFound {
+    name: String,
+    url: String,
+  },
+  #[error("Request to ({url}) for ({name}) was refused (status {status}); check the token{}.", .token.as_ref().map(|t| format!(" from ({})", t)).unwrap_or_default())]
+  Refused {
+    name: String,
+    url: String,
+    status: u16,
+    token: Option<String>,
+  },
+  #[error("Response from ({url}) for ({name}) is not valid: {reason}.")]
+  Malformed {
+    name: String,
+    url: String,
+    reason: String,
+  },
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Value from ({url}={value}) was not accepted. {hint}")]
+pub struct ValueNotAccepted {
+  name: String,
+  url: String,
+  value: String,
+  hint: String,
+  source: Box<dyn std::error::Error>,
+}
+
+// how the response to a request was obtained
+#[derive(Debug)]
+enum Fetched {
+  Service(String),
+  Cache(String, PathBuf),
+}
+
+// the values under the prefix, and the cache file they came from, if any
+type Table = (BTreeMap<String, Value>, Option<PathBuf>);
+
+/// A key-value service reached over HTTP.
+pub struct KeyValueService {
+  base_url: String,
+  prefix: Option<String>,
+  timeout: Duration,
+  token: Option<(String, String)>,
+  cache_dir: Option<PathBuf>,
+  // the table fetched for the prefix, once it has been fetched successfully
+  table: Mutex<Option<Table>>,
+  // the values under the prefix written to the cache
+  kept: Mutex<BTreeMap<String, Value>>,
+  // keys whose values came from the cache, for `describe`
+  cached: Mutex<BTreeSet<String>>,
+}
+
+// the token is secret, only the item it came from is shown
+impl std::fmt::Debug for KeyValueService {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.debug_struct("KeyValueService")
+      .field("base_url", &self.base_url)
+      .field("prefix", &self.prefix)
+      .field("timeout", &self.timeout)
+      .field("token", &self.token.as_ref().map(|(item, _)| item))
+      .field("cache_dir", &self.cache_dir)
+      .finish()
+  }
+}
+
+impl KeyValueService {
+  /// Fetch each item on its own from `base_url`, e.g. `http://127.0.0.1:8500/v1/kv/myapp`.
+  pub fn new(base_url: &str) -> Self {
+    Self {
+      base_url: base_url.trim_end_matches('/').into(),
+      prefix: None,
+      timeout: TIMEOUT,
+      token: None,
+      cache_dir: None,
+      table: Mutex::new(None),
+      kept: Mutex::new(BTreeMap::new()),
+      cached: Mutex::new(BTreeSet::new()),
+    }
+  }
+
+  /// Fetch every item in one request, as a JSON object at `prefix` under the base URL.
+  pub fn with_prefix(mut self, prefix: &str) -> Self {
+    self.prefix = Some(prefix.trim_matches('/').into());
+    self
+  }
+
+  /// Fail requests that take longer than this. Defaults to `TIMEOUT`.
+  pub fn with_timeout(mut self, timeout: Duration) -> Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Authenticate with the value of `token` as a bearer token, if it has one.
+  /// The token item should be resolved first, e.g. from the environment or a credential.
+  pub fn with_token_from(mut self, token: &dyn ConfigurationItem) -> Self {
+    self.token = token.get_value()
+      .and_then(|v| v.as_scalar_string())
+      .map(|t| (String::from(token.get_name()), t));
+    self
+  }
+
+  /// Keep responses in `dir`, and use them when the service is unavailable.
+  pub fn with_cache_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+    self.cache_dir = Some(dir.as_ref().to_path_buf());
+    self
+  }
+
+  // the path of the item (or prefix) under the base URL, e.g. `database/host`
+  fn key(&self, ci: &dyn ConfigurationItem) -> String {
+    match (&self.prefix, ci.get_group()) {
+      (Some(prefix), _) => prefix.clone(),
+      (None, Some(group)) => format!("{}/{}", group, schema::property_name(ci)),
+      (None, None) => schema::property_name(ci),
+    }
+  }
+
+  fn url(&self, key: &str) -> String {
+    format!("{}/{}", self.base_url, key)
+  }
+
+  // one file per key, named after the key with anything but letters, digits, `-` and `_` escaped
+  // as `%XX`, so that different keys never share a file and no key reaches outside the directory
+  fn cache_path(&self, key: &str) -> Option<PathBuf> {
+    let name: String = key.bytes()
+      .map(|b| match b {
+        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => char::from(b).to_string(),
+        _ => format!("%{:02X}", b),
+      })
+      .collect();
+    self.cache_dir.as_ref().map(|dir| dir.join(name))
+  }
+
+  // the cache only helps when the service is down, so failing to write it is not an error
+  fn store(path: &Path, body: &str) {
+    let _ = fs::create_dir_all(path.parent().unwrap_or_else(|| Path::new(""))).and_then(|_| fs::write(path, body));
+  }
+
+  // fetch the key, falling back to the cache when the service is unavailable
+  fn fetch(&self, name: &str, key: &str, cache: bool) -> Result<Fetched, Error> {
+    let url = self.url(key);
+    let agent = ureq::AgentBuilder::new().timeout(self.timeout).build();
+    let mut request = agent.get(&url);
+    if let Some((_, token)) = &self.token {
+      request = request.set("Authorization", &format!("Bearer {}", token));
+    }
+
+    let unavailable = match request.call() {
+      Ok(response) => {
+        let mut body = String::new();
+        let read = response.into_reader().take(MAX_RESPONSE_BYTES + 1).read_to_string(&mut body);
+        match read {
+          Err(e) => e.to_string(),
+          Ok(n) if n as u64 > MAX_RESPONSE_BYTES => return Err(Error::Malformed {
+            name: name.into(),
+            url: url,
+            reason: format!("larger than {} bytes", MAX_RESPONSE_BYTES),
+          }),
+          Ok(_) => {
+            if let (true, Some(path)) = (cache, self.cache_path(key)) {
+              Self::store(&path, &body);
+            }
+            return Ok(Fetched::Service(body));
+          }
+        }
+      }
+      Err(ureq::Error::Status(404, _)) => return Err(Error::KeyNotFound { name: name.into(), url: url }),
+      Err(ureq::Error::Status(status, _)) if status == 401 || status == 403 => return Err(Error::Refused {
+        name: name.into(),
+        url: url,
+        status: status,
+        token: self.token.as_ref().map(|(item, _)| item.clone()),
+      }),
+      Err(ureq::Error::Status(status, _)) => format!("status {}", status),
+      Err(e) => e.to_string(),
+    };
+
+    let cache = self.cache_path(key);
+    let cached = cache.as_ref().and_then(|path| {
+      let f = file::File::new(path, MAX_RESPONSE_BYTES);
+      let contents = String::from_utf8(f.contents()?.to_vec()).ok()?;
+      Some(Fetched::Cache(contents, path.clone()))
+    });
+    cached.ok_or(Error::CouldNotFetch { name: name.into(), url: url, reason: unavailable, cache: cache })
+  }
+
+  // look up the item in the table for the prefix, fetching it the first time; a failed fetch is
+  // not kept, so the next item tries again
+  fn lookup(&self, ci: &dyn ConfigurationItem) -> Result<(Option<Value>, Option<PathBuf>), Error> {
+    let mut table = self.table.lock().unwrap();
+    if table.is_none() {
+      let key = self.key(ci);
+      // written to the cache item by item instead, see `keep`
+      let (body, from) = match self.fetch(ci.get_name(), &key, false)? {
+        Fetched::Service(body) => (body, None),
+        Fetched::Cache(body, path) => (body, Some(path)),
+      };
+      let parsed = Format::Json.parse(&body)
+        .map_err(|reason| Error::Malformed { name: ci.get_name().into(), url: self.url(&key), reason: reason })?;
+      *table = Some((parsed, from));
+    }
+
+    let (table, from) = table.as_ref().unwrap();
+    let table = match ci.get_group() {
+      None => Some(table),
+      Some(group) => match table.get(group) {
+        Some(Value::Map(t)) => Some(t),
+        _ => None,
+      },
+    };
+    let value = table.and_then(|t| t.get(&schema::property_name(ci)).cloned());
+    if let (Some(value), None, false) = (&value, from, ci.is_secret()) {
+      self.keep(ci, value);
+    }
+    Ok((value, from.clone()))
+  }
+
+  // write the value of the item, with those of the items looked up before it, to the cache
+  fn keep(&self, ci: &dyn ConfigurationItem, value: &Value) {
+    let path = match self.cache_path(&self.key(ci)) {
+      Some(path) => path,
+      None => return,
+    };
+    let mut kept = self.kept.lock().unwrap();
+    let table = match ci.get_group() {
+      None => &mut *kept,
+      Some(group) => match kept.entry(group.to_string()).or_insert_with(|| Value::Map(BTreeMap::new())) {
+        Value::Map(t) => t,
+        _ => return,
+      },
+    };
+    table.insert(schema::property_name(ci), value.clone());
+    Self::store(&path, &Value::Map(kept.clone()).to_json());
+  }
+}
+
+impl ConfigurationValueSource for KeyValueService {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    let name = String::from(ci.get_name());
+    let file_key = schema::file_key(ci);
+    let url = self.url(&self.key(ci));
+
+    // items accept a `Box<String>` or a `Box<Value>`, see `ConfigurationItem::try_value`
+    let text = |body: &str| Box::new(Box::new(file::trim_newlines(body).to_string())) as Box<dyn std::any::Any>;
+    let (value, from) = match &self.prefix {
+      None => match self.fetch(&name, &self.key(ci), !ci.is_secret()) {
+        Err(e) => return Some(Box::new(e)),
+        Ok(Fetched::Service(body)) => (text(&body), None),
+        Ok(Fetched::Cache(body, path)) => (text(&body), Some(path)),
+      },
+      Some(_) => match self.lookup(ci) {
+        Err(e) => return Some(Box::new(e)),
+        Ok((None, _)) => return Some(Box::new(Error::KeyNotFound { name: name, url: format!("{}#{}", url, file_key) })),
+        Ok((Some(value), from)) => (Box::new(Box::new(value)) as Box<dyn std::any::Any>, from),
+      },
+    };
+
+    let mut cached = self.cached.lock().unwrap();
+    match from {
+      Some(_) => cached.insert(file_key),
+      None => cached.remove(&file_key),
+    };
+
+    let shown = if ci.is_secret() {
+      dump::REDACTED.to_string()
+    } else if let Some(s) = value.downcast_ref::<Box<String>>() {
+      s.to_string()
+    } else {
+      value.downcast_ref::<Box<Value>>().map(|v| v.to_json_inline()).unwrap_or_default()
+    };
+    ci.try_value(value.as_ref()).map(|e| Box::new(ValueNotAccepted {
+      name: name,
+      url: url,
+      value: shown,
+      hint: crate::metadata::hint(ci, e.as_ref()),
+      source: e,
+    }) as Box<dyn std::error::Error>)
+  }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    let key = self.key(ci);
+    let described = match &self.prefix {
+      None => format!("kv {}", self.url(&key)),
+      Some(_) => format!("kv {} key {}", self.url(&key), schema::file_key(ci)),
+    };
+    match self.cache_path(&key) {
+      Some(path) if self.cached.lock().unwrap().contains(&schema::file_key(ci)) =>
+        format!("{} (service unavailable, cached in {})", described, path.display()),
+      _ => described,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use std::io::BufRead;
+  use std::io::BufReader;
+  use std::io::Write;
+  use std::net::TcpListener;
+  use crate::config;
+  use crate::testing::TempDir;
+
+  config!(
+    (Port u16),
+    #[group = "database"]
+    (Host String),
+    #[secret]
+    (KvToken String)
+  );
+
+  // a stand-in for the service, answering requests for `routes` until the test ends;
+  // requests must carry `token` as a bearer token, if given
+  fn serve(routes: Vec<(&'static str, &'static str)>, token: Option<&'static str>) -> String {
+    serve_on(TcpListener::bind("127.0.0.1:0").unwrap(), routes, token)
+  }
+
+  fn serve_on(listener: TcpListener, routes: Vec<(&'static str, &'static str)>, token: Option<&'static str>) -> String {
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let mut stream = stream.unwrap();
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines().map(|l| l.unwrap());
+        let path = lines.next().unwrap().split(' ').nth(1).unwrap().to_string();
+        let headers: Vec<String> = lines.take_while(|l| !l.is_empty()).collect();
+        let authorized = token.is_none_or(|t| headers.iter().any(|h| h == &format!("Authorization: Bearer {}", t)));
+
+        let (status, body) = match routes.iter().find(|(p, _)| *p == path) {
+          _ if !authorized => ("401 Unauthorized", ""),
+          Some((_, body)) => ("200 OK", *body),
+          None => ("404 Not Found", ""),
+        };
+        let _ = write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+      }
+    });
+    base_url
+  }
+
+  // a URL where nothing is listening
+  fn unavailable() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}/v1/kv", listener.local_addr().unwrap())
+  }
+
+  #[test]
+  fn keys_are_fetched() {
+    let base_url = serve(vec![("/v1/kv/port", "8080\n"), ("/v1/kv/database/host", "db.internal")], Some("s3cret"));
+    let mut token = KvToken::new(Some("s3cret".into()));
+    let kv = KeyValueService::new(&format!("{}/v1/kv/", base_url)).with_token_from(&token);
+    let debug = format!("{:?}", kv);
+    assert!(debug.contains("token: Some(\"KvToken\")") && !debug.contains("s3cret"));
+
+    let mut port = Port::new(None);
+    assert!(kv.try_get(&mut port).is_none());
+    assert_eq!(port.get(), Some(&8080));
+    assert_eq!(kv.describe(&port), format!("kv {}/v1/kv/port", base_url));
+
+    let mut host = Host::new(None);
+    assert!(kv.try_get(&mut host).is_none());
+    assert_eq!(host.get(), Some(&String::from("db.internal")));
+
+    let e = kv.try_get(&mut token).unwrap();
+    assert_eq!(e.to_string(), format!("Key for (KvToken) not found at ({}/v1/kv/kv_token).", base_url));
+
+    let kv = KeyValueService::new(&format!("{}/v1/kv", base_url)).with_token_from(&KvToken::new(Some("wrong".into())));
+    let e = kv.try_get(&mut port).unwrap();
+    assert_eq!(e.to_string(), format!("Request to ({}/v1/kv/port) for (Port) was refused (status 401); check the token from (KvToken).", base_url));
+  }
+
+  #[test]
+  fn items_are_looked_up_under_a_prefix() {
+    let base_url = serve(vec![("/v1/kv/myapp", r#"{"port": 8080, "database": {"host": "db.internal"}}"#)], None);
+    let kv = KeyValueService::new(&format!("{}/v1/kv", base_url)).with_prefix("myapp");
+
+    let mut host = Host::new(None);
+    assert!(kv.try_get(&mut host).is_none());
+    assert_eq!(host.get(), Some(&String::from("db.internal")));
+    assert_eq!(kv.describe(&host), format!("kv {}/v1/kv/myapp key database.host", base_url));
+
+    let mut port = Port::new(None);
+    assert!(kv.try_get(&mut port).is_none());
+    assert_eq!(port.get(), Some(&8080));
+  }
+
+  #[test]
+  fn cache_is_used_when_the_service_is_unavailable() {
+    let cache = TempDir::new("kv");
+    let base_url = serve(vec![("/v1/kv/port", "8080")], None);
+    let mut port = Port::new(None);
+    assert!(KeyValueService::new(&format!("{}/v1/kv", base_url)).with_cache_dir(&cache).try_get(&mut port).is_none());
+
+    let down = unavailable();
+    let kv = KeyValueService::new(&down).with_cache_dir(&cache);
+    let mut port = Port::new(None);
+    assert!(kv.try_get(&mut port).is_none());
+    assert_eq!(port.get(), Some(&8080));
+    assert_eq!(kv.describe(&port), format!("kv {}/port (service unavailable, cached in {})", down, cache.join("port").display()));
+
+    let mut host = Host::new(None);
+    let e = kv.try_get(&mut host).unwrap();
+    assert!(e.to_string().starts_with(&format!("Could not fetch ({}/database/host) for (Host): ", down)));
+    assert!(e.to_string().ends_with(&format!("; no cached copy in ({}).", cache.join("database%2Fhost").display())));
+  }
+
+  #[test]
+  fn secrets_under_a_prefix_are_not_cached() {
+    let cache = TempDir::new("kv-prefix");
+    let base_url = serve(vec![("/v1/kv/myapp", r#"{"port": 8080, "kv_token": "s3cret"}"#)], None);
+    let kv = KeyValueService::new(&format!("{}/v1/kv", base_url)).with_prefix("myapp").with_cache_dir(&cache);
+    assert!(kv.try_get(&mut KvToken::new(None)).is_none());
+    assert!(kv.try_get(&mut Port::new(None)).is_none());
+
+    let contents = std::fs::read_to_string(cache.join("myapp")).unwrap();
+    assert!(contents.contains("8080"));
+    assert!(!contents.contains("s3cret"));
+
+    let kv = KeyValueService::new(&unavailable()).with_prefix("myapp").with_cache_dir(&cache);
+    let mut port = Port::new(None);
+    assert!(kv.try_get(&mut port).is_none());
+    assert_eq!(port.get(), Some(&8080));
+    assert!(kv.try_get(&mut KvToken::new(None)).unwrap().to_string().starts_with("Key for (KvToken) not found"));
+  }
+
+  #[test]
+  fn failed_fetches_are_retried() {
+    // accepts connections, but does not answer until it is served
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let kv = KeyValueService::new(&format!("http://{}/v1/kv", listener.local_addr().unwrap()))
+      .with_prefix("myapp")
+      .with_timeout(Duration::from_millis(100));
+    let mut port = Port::new(None);
+    assert!(kv.try_get(&mut port).is_some());
+
+    serve_on(listener, vec![("/v1/kv/myapp", r#"{"port": 8080}"#)], None);
+    assert!(kv.try_get(&mut port).is_none());
+    assert_eq!(port.get(), Some(&8080));
+  }
+
+  #[test]
+  fn keys_have_their_own_cache_files() {
+    let kv = KeyValueService::new("http://127.0.0.1").with_cache_dir("/cache");
+    assert_ne!(kv.cache_path("a/b.c"), kv.cache_path("a.b/c"));
+    assert_eq!(kv.cache_path("../etc"), Some(PathBuf::from("/cache/%2E%2E%2Fetc")));
+  }
+
+  #[test]
+  fn requests_time_out() {
+    // accepts connections, but never answers
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let kv = KeyValueService::new(&format!("http://{}", listener.local_addr().unwrap()))
+      .with_timeout(Duration::from_millis(100));
+    let mut port = Port::new(None);
+    let e = kv.try_get(&mut port).unwrap();
+    assert!(e.to_string().starts_with(&format!("Could not fetch (http://{}/port) for (Port): ", listener.local_addr().unwrap())));
+  }
+}
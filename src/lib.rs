@@ -1,6 +1,36 @@
+// Struct fields are initialised as `field: field` throughout.
+#![allow(clippy::redundant_field_names)]
+
+pub mod bootstrap;
+pub mod choice;
+pub mod command;
+pub mod command_line;
+pub mod config_file;
+pub mod constraint;
+pub mod credentials;
+pub mod defaults;
+pub mod directory;
+pub mod discovery;
+pub mod docs;
+pub mod dump;
+pub mod encrypted;
 pub mod environment;
-// pub mod file;
+pub mod file;
+pub mod fragments;
+pub mod human;
+pub mod interpolate;
+pub mod kv;
+pub mod merge;
+pub mod metadata;
+pub mod path;
+pub mod profile;
+pub mod sample;
+pub mod schema;
+pub mod validation;
+pub mod value;
 // pub mod cli;
+#[cfg(test)]
+mod testing;
 
 use std::any::Any;
 use std::error::Error;
@@ -8,38 +38,24 @@ use std::ops::Deref;
 use std::ops::DerefMut;
 use std::sync::RwLock;
 use std::sync::RwLockWriteGuard;
-use std::convert::TryInto;
-use std::convert::TryFrom;
-use std::str::FromStr;
 use std::fmt::Debug;
 
 use auto_impl::auto_impl;
 use derive_new::new;
-use impls::impls;
 
 /// Basically the same as `TryInto`, but the result is behind a trait object.
 pub trait TryIntoDynErr<T> {
   fn try_into_dyn_err(&self) -> Result<T, Box<dyn Error>>;
 }
 
-impl<B, E> TryIntoDynErr<B> for String
+/// Strings are converted using `ConfigurationValue::parse`.
+impl<B> TryIntoDynErr<B> for String
 where
-  B: FromStr<Err=E>,
-  E: Error + 'static,
+  B: value::ConfigurationValue,
 {
   fn try_into_dyn_err(&self) -> Result<B, Box<dyn Error>> {
-    match B::from_str(self) {
-      Ok(b) => Ok(b),
-      Err(e) => Err(Box::new(e)),
-    }
-  }
-}
-
-trait ITryGet {
-  pub fn try_convert(&self, thing: Thing) -> Option<Thing> {
+    B::parse(self)
   }
-   
-  // and then auto impl this for a bunch of things
 }
 
 // http://idubrov.name/rust/2018/06/16/dynamic-casting-traits.html
@@ -50,55 +66,273 @@ trait ITryGet {
 // https://bennetthardwick.com/rust/downcast-trait-object/
 // https://users.rust-lang.org/t/downcast-to-box-trait/4331/2
 
+/// Returned by `ConfigurationItem::try_value` when the value is not of a type the item understands.
 #[derive(Debug, derive_more::Display)]
-struct ValueNotHandled;
+#[display(fmt = "The value is not of a type this item can use.")]
+pub struct ValueNotHandled;
 
 impl Error for ValueNotHandled {}
 
+/// Returned by `ConfigurationItem::try_default` when the item does not declare a default.
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "No default value was declared for this item.")]
+pub struct NoDefault;
+
+impl Error for NoDefault {}
+
+/// Returned by a source to say the item was deliberately left without a value, e.g. by `null` in a
+/// configuration file or `--no-foo` on the command line.
+/// Lower-priority sources, including defaults, are not consulted.
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "The value was explicitly unset.")]
+pub struct Unset;
+
+impl Error for Unset {}
+
+/// Declare configuration items.
+///
+/// Each item may be preceded by attributes:
+///
+/// - doc comments, which become the item's description;
+/// - `#[group = "name"]`, which places the item in a group;
+/// - `#[default = expr]`, the value to use when no source provides one;
+/// - `#[secret]`, for items whose values must not be displayed;
+/// - `#[example = expr]`, an example value (may be repeated);
+/// - `#[units = "seconds"]`, the units the value is measured in;
+/// - `#[since = "1.2.0"]`, the version the item was introduced in;
+/// - `#[advanced]` or `#[hidden]`, to control how prominently the item is documented;
+/// - `#[volatile]`, for items whose values may change while the process runs, which sources that
+///   cache values, such as `command::Command`, fetch every time;
+/// - `#[min = expr]`, `#[max = expr]`, `#[min_length = n]`, `#[max_length = n]`,
+///   `#[pattern = "regex"]`, `#[one_of(expr, ...)]` and `#[non_empty]`, constraints that values
+///   must satisfy (see `constraint::Constraint`);
+/// - `#[exists]`, `#[is_file]`, `#[is_dir]` and `#[readable]`, checks made against path items when
+///   they are loaded (see `path::Check`);
+/// - `#[merge(append)]`, `#[merge(prepend)]` or `#[merge(deep)]`, to combine list or map values
+///   from every source instead of using the highest-priority one (see `merge::Merge`). Lists take
+///   `append` or `prepend` and maps take `deep`; other items do not compile.
 #[macro_export]
 macro_rules! config {
-  ($name:ident, $type:ty) => {
-    #[derive(new, Debug)]
+  (@check [doc = $d:expr]) => {};
+  (@check [group = $g:expr]) => {};
+  (@check [default = $e:expr]) => {};
+  (@check [secret]) => {};
+  (@check [example = $e:expr]) => {};
+  (@check [units = $u:expr]) => {};
+  (@check [since = $v:expr]) => {};
+  (@check [advanced]) => {};
+  (@check [hidden]) => {};
+  (@check [volatile]) => {};
+  (@check [min = $e:expr]) => {};
+  (@check [max = $e:expr]) => {};
+  (@check [min_length = $e:expr]) => {};
+  (@check [max_length = $e:expr]) => {};
+  (@check [pattern = $e:expr]) => {};
+  (@check [one_of($($e:expr),* $(,)?)]) => {};
+  (@check [non_empty]) => {};
+  (@check [exists]) => {};
+  (@check [is_file]) => {};
+  (@check [is_dir]) => {};
+  (@check [readable]) => {};
+  (@check [merge(append)]) => {};
+  (@check [merge(prepend)]) => {};
+  (@check [merge(deep)]) => {};
+  (@check [$($other:tt)*]) => {
+    compile_error!(concat!("unsupported configuration item attribute: ", stringify!($($other)*)));
+  };
+
+  (@metadata $m:ident $type:ty, [doc = $d:expr]) => { $m.push_description($d); };
+  (@metadata $m:ident $type:ty, [example = $e:expr]) => {
+    let example: $type = $crate::value::Literal::convert($e);
+    $m.examples.push($crate::value::ConfigurationValue::to_value(&example));
+  };
+  (@metadata $m:ident $type:ty, [units = $u:expr]) => { $m.units = Some($u); };
+  (@metadata $m:ident $type:ty, [since = $v:expr]) => { $m.since = Some($v); };
+  (@metadata $m:ident $type:ty, [advanced]) => { $m.visibility = $crate::metadata::Visibility::Advanced; };
+  (@metadata $m:ident $type:ty, [hidden]) => { $m.visibility = $crate::metadata::Visibility::Hidden; };
+  (@metadata $m:ident $type:ty, [volatile]) => { $m.volatile = true; };
+  (@metadata $m:ident $type:ty, [$($other:tt)*]) => {};
+
+  (@group $group:ident [group = $g:expr]) => { $group = Some($g); };
+  (@group $group:ident [$($other:tt)*]) => {};
+
+  (@default $default:ident [default = $e:expr]) => { $default = Some($crate::value::Literal::convert($e)); };
+  (@default $default:ident [$($other:tt)*]) => {};
+
+  (@constraint $c:ident $type:ty, [min = $e:expr]) => {
+    let min: $type = $crate::value::Literal::convert($e);
+    $c.push($crate::constraint::Constraint::Minimum($crate::value::ConfigurationValue::to_value(&min)));
+  };
+  (@constraint $c:ident $type:ty, [max = $e:expr]) => {
+    let max: $type = $crate::value::Literal::convert($e);
+    $c.push($crate::constraint::Constraint::Maximum($crate::value::ConfigurationValue::to_value(&max)));
+  };
+  (@constraint $c:ident $type:ty, [min_length = $e:expr]) => { $c.push($crate::constraint::Constraint::MinLength($e)); };
+  (@constraint $c:ident $type:ty, [max_length = $e:expr]) => { $c.push($crate::constraint::Constraint::MaxLength($e)); };
+  (@constraint $c:ident $type:ty, [pattern = $e:expr]) => { $c.push($crate::constraint::Constraint::Pattern($e)); };
+  (@constraint $c:ident $type:ty, [one_of($($e:expr),* $(,)?)]) => {
+    $c.push($crate::constraint::Constraint::OneOf(vec![$({
+      let v: $type = $crate::value::Literal::convert($e);
+      $crate::value::ConfigurationValue::to_value(&v)
+    }),*]));
+  };
+  (@constraint $c:ident $type:ty, [non_empty]) => { $c.push($crate::constraint::Constraint::NonEmpty); };
+  (@constraint $c:ident $type:ty, [exists]) => { $c.push($crate::constraint::Constraint::Path($crate::path::Check::Exists)); };
+  (@constraint $c:ident $type:ty, [is_file]) => { $c.push($crate::constraint::Constraint::Path($crate::path::Check::IsFile)); };
+  (@constraint $c:ident $type:ty, [is_dir]) => { $c.push($crate::constraint::Constraint::Path($crate::path::Check::IsDirectory)); };
+  (@constraint $c:ident $type:ty, [readable]) => { $c.push($crate::constraint::Constraint::Path($crate::path::Check::Readable)); };
+  (@constraint $c:ident $type:ty, [$($other:tt)*]) => {};
+
+  (@secret $secret:ident [secret]) => { $secret = true; };
+  (@secret $secret:ident [$($other:tt)*]) => {};
+
+  (@merge $merge:ident $type:ty, [merge(append)]) => {
+    let _ = $crate::merge::sequence::<$type>;
+    $merge = $crate::merge::Merge::Append;
+  };
+  (@merge $merge:ident $type:ty, [merge(prepend)]) => {
+    let _ = $crate::merge::sequence::<$type>;
+    $merge = $crate::merge::Merge::Prepend;
+  };
+  (@merge $merge:ident $type:ty, [merge(deep)]) => {
+    let _ = $crate::merge::table::<$type>;
+    $merge = $crate::merge::Merge::Deep;
+  };
+  (@merge $merge:ident $type:ty, [$($other:tt)*]) => {};
+
+  ($(#[$($attr:tt)*])* $name:ident, $type:ty) => {
+    $($crate::config!(@check [$($attr)*]);)*
+
+    #[derive(Debug)]
     pub struct $name(Option<$type>);
 
+    #[allow(dead_code)]
     impl $name {
+      pub fn new(value: Option<$type>) -> Self {
+        $name(value)
+      }
+
       pub fn get(&self) -> Option<&$type> {
         self.0.as_ref()
       }
+
+      /// Return the declared default value for this item, if any.
+      #[allow(unused_mut, unused_assignments)]
+      pub fn default_value() -> Option<$type> {
+        let mut default: Option<$type> = None;
+        $($crate::config!(@default default [$($attr)*]);)*
+        default
+      }
     }
 
-    impl ConfigurationItem for $name {
+    impl $crate::ConfigurationItem for $name {
       fn get_name(&self) -> &str {
         stringify!($name)
       }
 
+      #[allow(unused_mut, unused_assignments)]
       fn get_group(&self) -> Option<&str> {
-        None
+        let mut group: Option<&'static str> = None;
+        $($crate::config!(@group group [$($attr)*]);)*
+        group
+      }
+
+      fn get_kind(&self) -> $crate::value::Kind {
+        <$type as $crate::value::ConfigurationValue>::kind()
+      }
+
+      #[allow(unused_mut)]
+      fn get_metadata(&self) -> $crate::metadata::Metadata {
+        let mut metadata = $crate::metadata::Metadata::default();
+        $($crate::config!(@metadata metadata $type, [$($attr)*]);)*
+        metadata
       }
 
-      fn try_value(&mut self, value: &dyn Any) -> Option<Box<dyn Error>> {
-        if let Some(x) = value.downcast_ref::<Box<$type>>() {
-          self.0 = Some(x.deref().clone());
-          return None;
+      #[allow(unused_mut, unused_assignments)]
+      fn is_secret(&self) -> bool {
+        let mut secret = false;
+        $($crate::config!(@secret secret [$($attr)*]);)*
+        secret
+      }
+
+      #[allow(unused_mut, unused_assignments)]
+      fn get_merge(&self) -> $crate::merge::Merge {
+        let mut merge = $crate::merge::Merge::Replace;
+        $($crate::config!(@merge merge $type, [$($attr)*]);)*
+        merge
+      }
+
+      fn get_value(&self) -> Option<$crate::value::Value> {
+        self.0.as_ref().map($crate::value::ConfigurationValue::to_value)
+      }
+
+      fn get_default(&self) -> Option<$crate::value::Value> {
+        Self::default_value().map(|d| $crate::value::ConfigurationValue::to_value(&d))
+      }
+
+      fn try_default(&mut self) -> Option<Box<dyn std::error::Error>> {
+        match Self::default_value() {
+          Some(d) => {
+            self.0 = Some(d);
+            None
+          }
+          None => Some(Box::new($crate::NoDefault)),
         }
+      }
+
+      #[allow(unused_mut, clippy::vec_init_then_push)]
+      fn get_constraints(&self) -> Vec<$crate::constraint::Constraint> {
+        let mut constraints = Vec::new();
+        $($crate::config!(@constraint constraints $type, [$($attr)*]);)*
+        constraints
+      }
 
-        // this doesn't work :(
-        if let Some(x) = value.downcast_ref::<Box<dyn TryIntoDynErr<$type>>>() {
-          match x.try_into_dyn_err() {
-            Ok(val) => {
-              self.0 = Some(val);
-              return None;
+      fn try_value(&mut self, value: &dyn std::any::Any) -> Option<Box<dyn std::error::Error>> {
+        // errors about the values of secret items must not show them
+        let secret = $crate::ConfigurationItem::is_secret(self);
+        let parsed: $type =
+          if let Some(x) = value.downcast_ref::<Box<$type>>() {
+            std::ops::Deref::deref(x).clone()
+          }
+          else if let Some(x) = value.downcast_ref::<Box<String>>() {
+            match <String as $crate::TryIntoDynErr<$type>>::try_into_dyn_err(x) {
+              Ok(val) => val,
+              Err(e) => return Some($crate::constraint::redact_if(secret, e)),
+            }
+          }
+          else if let Some(x) = value.downcast_ref::<Box<std::ffi::OsString>>() {
+            match <$type as $crate::value::ConfigurationValue>::from_os_str(x) {
+              Ok(val) => val,
+              Err(e) => return Some($crate::constraint::redact_if(secret, e)),
+            }
+          }
+          else if let Some(x) = value.downcast_ref::<Box<$crate::value::Value>>() {
+            match <$type as $crate::value::ConfigurationValue>::from_value(x) {
+              Ok(val) => val,
+              Err(e) => return Some($crate::constraint::redact_if(secret, e)),
             }
-            Err(e) => return Some(e),
+          }
+          else {
+            return Some(Box::new($crate::ValueNotHandled));
+          };
+
+        let constraints = self.get_constraints();
+        if let Err(e) = $crate::constraint::check_patterns(&constraints) {
+          return Some(Box::new(e));
+        }
+        if !constraints.is_empty() {
+          if let Err(e) = $crate::constraint::check_value(&parsed, &constraints) {
+            return Some($crate::constraint::redact_if(secret, Box::new(e)));
           }
         }
 
-        Some(Box::new(crate::ValueNotHandled {}))
+        self.0 = Some(parsed);
+        None
       }
     }
   };
-  ($(($name:ident $type:ty)),*) => {
-    $(config!($name, $type);),*
+  ($($(#[$($attr:tt)*])* ($name:ident $type:ty)),* $(,)?) => {
+    $($crate::config!($(#[$($attr)*])* $name, $type);)*
   }
 }
 
@@ -110,15 +344,67 @@ pub trait ConfigurationItem: Debug {
   /// Return the "group" for the configuration item.
   fn get_group(&self) -> Option<&str>;
 
+  /// Return the kind of value this item holds.
+  fn get_kind(&self) -> value::Kind;
+
+  /// Return descriptive information about the item.
+  fn get_metadata(&self) -> metadata::Metadata {
+    metadata::Metadata::default()
+  }
+
+  /// Return `true` if the value of the item must not be displayed.
+  fn is_secret(&self) -> bool {
+    false
+  }
+
+  /// Return how values from several sources are combined.
+  fn get_merge(&self) -> merge::Merge {
+    merge::Merge::Replace
+  }
+
+  /// Return the current value of the item, if it has one.
+  fn get_value(&self) -> Option<value::Value> {
+    None
+  }
+
+  /// Return the default value for the item, if it has one.
+  fn get_default(&self) -> Option<value::Value> {
+    None
+  }
+
+  /// Use the default value for the item.
+  /// Should return `None` if the item has a default, otherwise return an `Error`.
+  fn try_default(&mut self) -> Option<Box<dyn Error>> {
+    Some(Box::new(NoDefault))
+  }
+
+  /// Return the constraints that values for this item must satisfy.
+  fn get_constraints(&self) -> Vec<constraint::Constraint> {
+    Vec::new()
+  }
+
   /// Try to use the provided value.
+  /// Sources provide a `Box<String>` for values written as text, a `Box<OsString>` for values from
+  /// the operating system that are not valid unicode, or a `Box<value::Value>` for structured values
+  /// such as lists and maps.
   /// Should return `None` if the value is ok to use, otherwise return an `Error`
   /// explaining why it's not usable.
+  /// Values that can be parsed but do not satisfy the item's constraints should be rejected with
+  /// `constraint::Invalid`.
   fn try_value(&mut self, value: &dyn Any) -> Option<Box<dyn Error>>;
 }
 
 impl<'b> ConfigurationItem for RwLockWriteGuard<'_, &mut (dyn ConfigurationItem + 'b)> {
   fn get_name(&self) -> &str { self.deref().get_name() }
   fn get_group(&self) -> Option<&str> { self.deref().get_group() }
+  fn get_kind(&self) -> value::Kind { self.deref().get_kind() }
+  fn get_metadata(&self) -> metadata::Metadata { self.deref().get_metadata() }
+  fn is_secret(&self) -> bool { self.deref().is_secret() }
+  fn get_merge(&self) -> merge::Merge { self.deref().get_merge() }
+  fn get_value(&self) -> Option<value::Value> { self.deref().get_value() }
+  fn get_default(&self) -> Option<value::Value> { self.deref().get_default() }
+  fn try_default(&mut self) -> Option<Box<dyn Error>> { self.deref_mut().try_default() }
+  fn get_constraints(&self) -> Vec<constraint::Constraint> { self.deref().get_constraints() }
   fn try_value(&mut self, value: &dyn Any) -> Option<Box<dyn Error>> { self.deref_mut().try_value(value) }
 }
 
@@ -126,6 +412,16 @@ impl<'b> ConfigurationItem for RwLockWriteGuard<'_, &mut (dyn ConfigurationItem
 pub trait ConfigurationValueSource: Debug {
   /// Attempt to retrieve a value for the specified configuration item from this source.
   fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn Error>>;
+
+  /// Describe where this source looks for a value for the specified item,
+  /// e.g. "env APPNAME_MY_THRESHOLD".
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String;
+
+  /// Return `true` if the value this source provided for the item must not be displayed, even
+  /// though the item is not secret, e.g. because it was decrypted.
+  fn is_secret(&self, _ci: &dyn ConfigurationItem) -> bool {
+    false
+  }
 }
 
 /// Represents an attempt to get a `T` from the `ConfigurationValueSource`.
@@ -138,9 +434,43 @@ pub struct Attempt<'b> {
   error: Option<Box<dyn Error>>,
 }
 
+/// The result of an `Attempt`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  /// The source provided a value, and the item accepted it.
+  Accepted,
+  /// The source provided a value which was parsed, but did not satisfy the item's constraints.
+  /// Lower-priority sources are not consulted.
+  Invalid,
+  /// The source explicitly unset the item (see `Unset`).
+  /// Lower-priority sources are not consulted.
+  Unset,
+  /// The source did not provide a usable value.
+  Failed,
+}
+
 impl<'a> Attempt<'a> {
   pub fn is_ok(&self) -> bool {
-    self.error.is_some()
+    self.error.is_none()
+  }
+
+  pub fn outcome(&self) -> Outcome {
+    match &self.error {
+      None => Outcome::Accepted,
+      Some(e) if e.is::<Unset>() => Outcome::Unset,
+      Some(e) => match constraint::find_invalid(e.as_ref()) {
+        Some(_) => Outcome::Invalid,
+        None => Outcome::Failed,
+      },
+    }
+  }
+
+  pub fn source(&self) -> &'a dyn ConfigurationValueSource {
+    self.source
+  }
+
+  pub fn error(&self) -> Option<&(dyn Error + 'static)> {
+    self.error.as_deref()
   }
 }
 
@@ -152,6 +482,22 @@ pub struct Attempts<'a, 'b> {
 
   // the attempts
   attempts: Vec<Attempt<'b>>,
+
+  // for merged items, where each element came from
+  #[new(default)]
+  origins: Vec<merge::Origin>,
+
+  // for merged items, why the merged value was not accepted
+  #[new(default)]
+  merge_error: Option<Box<dyn Error>>,
+
+  // references expanded in the value, see `interpolate`
+  #[new(default)]
+  expansions: Vec<String>,
+
+  // whether any of the expanded references was secret
+  #[new(default)]
+  expanded_secret: bool,
 }
 
 impl<'a, 'b> Attempts<'a, 'b> {
@@ -160,7 +506,101 @@ impl<'a, 'b> Attempts<'a, 'b> {
     self
   }
 
-  // TODO: some kinda "print_report" or something
+  pub fn item(&self) -> &'a dyn ConfigurationItem {
+    self.item
+  }
+
+  pub fn attempts(&self) -> &[Attempt<'b>] {
+    &self.attempts
+  }
+
+  /// Return the attempt that provided the value, if any.
+  pub fn successful(&self) -> Option<&Attempt<'b>> {
+    self.attempts.iter().find(|a| a.is_ok())
+  }
+
+  /// Return `true` if the value must not be displayed, because the item is secret, a source that
+  /// provided it says so, or a secret was expanded into it (see `interpolate`).
+  pub fn is_secret(&self) -> bool {
+    self.item.is_secret()
+      || self.expanded_secret
+      || self.attempts.iter().any(|a| a.is_ok() && a.source.is_secret(self.item))
+  }
+
+  /// Describe where the value came from, e.g. "env APPNAME_MY_THRESHOLD".
+  /// Merged values name every source that contributed, e.g. "file config.toml key hosts and env APPNAME_HOSTS".
+  pub fn provenance(&self) -> Option<String> {
+    if self.origins.is_empty() {
+      return self.successful().map(|a| a.source.describe(self.item));
+    }
+
+    let mut sources: Vec<&str> = Vec::new();
+    for origin in self.origins.iter() {
+      if !sources.contains(&origin.source.as_str()) {
+        sources.push(&origin.source);
+      }
+    }
+    Some(sources.join(" and "))
+  }
+
+  /// Return the attempt that explicitly unset the item, if any.
+  pub fn unset(&self) -> Option<&Attempt<'b>> {
+    self.attempts.iter().find(|a| a.outcome() == Outcome::Unset)
+  }
+
+  /// For merged items, where each element of the value came from.
+  pub fn origins(&self) -> &[merge::Origin] {
+    &self.origins
+  }
+
+  /// For merged items, why the merged value was not accepted.
+  /// When this is set the item holds the value from the highest-priority source.
+  pub fn merge_error(&self) -> Option<&(dyn Error + 'static)> {
+    self.merge_error.as_deref()
+  }
+
+  /// Describe each reference expanded in the value, e.g. `${base_url} = "https://example.com" from
+  /// env APPNAME_BASE_URL`.
+  pub fn expansions(&self) -> &[String] {
+    &self.expansions
+  }
+
+  /// Explain how the value was resolved: each source consulted, in order, and what happened.
+  pub fn report(&self) -> String {
+    let item = self.item;
+    let mut out = String::new();
+    out.push_str(&format!("{}:\n", schema::file_key(item)));
+    for attempt in self.attempts.iter() {
+      let what = match (attempt.outcome(), attempt.error()) {
+        (Outcome::Accepted, _) => String::from("accepted"),
+        (Outcome::Invalid, Some(e)) => format!("invalid: {}", e),
+        (Outcome::Unset, _) => String::from("explicitly unset"),
+        (_, Some(e)) => format!("not used: {}", e),
+        (_, None) => unreachable!(),
+      };
+      out.push_str(&format!("  {}: {}\n", attempt.source.describe(item), what));
+    }
+    if self.attempts.is_empty() {
+      out.push_str("  no sources were consulted\n");
+    }
+    if !self.origins.is_empty() {
+      out.push_str(&format!("  merged ({}):\n", item.get_merge()));
+      for origin in self.origins.iter() {
+        let value = if self.is_secret() { dump::REDACTED.to_string() } else { origin.value.to_json_inline() };
+        out.push_str(&format!("    {} = {} from {}\n", origin.element, value, origin.source));
+      }
+    }
+    if !self.expansions.is_empty() {
+      out.push_str("  expanded:\n");
+      for step in self.expansions.iter() {
+        out.push_str(&format!("    {}\n", step));
+      }
+    }
+    if let Some(e) = &self.merge_error {
+      out.push_str(&format!("  merged value not accepted: {}\n", e));
+    }
+    out
+  }
 }
 
 /// Collection of sources to attempt to load values from.
@@ -172,26 +612,65 @@ pub struct ConfigurationStrategy<'a> {
 impl<'a> ConfigurationStrategy<'a> {
   /// Try to get a value for the specified `ConfigurationItem` using this strategy.
   pub fn try_get<'b>(&'a self, ci: &'b mut dyn ConfigurationItem) -> Attempts<'b, 'a> {
+    self.try_get_with(ci, |_, source, ci| source.try_get(ci))
+  }
+
+  // like `try_get`, but asks `get` to consult each source, given its index
+  pub(crate) fn try_get_with<'b, F>(&'a self, ci: &'b mut dyn ConfigurationItem, mut get: F) -> Attempts<'b, 'a>
+  where
+    F: FnMut(usize, &'a dyn ConfigurationValueSource, &mut dyn ConfigurationItem) -> Option<Box<dyn Error>>,
+  {
     let mut attempts = Vec::with_capacity(self.sources.len());
 
+    // collection items with a merge policy take a value from every source
+    let policy = ci.get_merge();
+    let merging = policy != merge::Merge::Replace && ci.get_kind().is_collection();
+    let mut layers = Vec::new();
+
     // so that we can pass a "temporary" mutable reference to source.try_get
     let lock = RwLock::new(ci);
 
-    for source in self.sources.iter() {
+    for (i, source) in self.sources.iter().enumerate() {
       let result = {
         let mut guard = lock.write().unwrap();
-        source.try_get(&mut guard)
+        get(i, *source, &mut guard)
       };
       let attempt = Attempt::new(source, result);
-      let stop = attempt.is_ok();
+      let outcome = attempt.outcome();
       attempts.push(attempt);
 
-      if stop {
+      if merging && outcome == Outcome::Accepted {
+        let guard = lock.read().unwrap();
+        if let Some(value) = guard.get_value() {
+          layers.push((value, source.describe(&**guard)));
+        }
+        continue;
+      }
+
+      if outcome != Outcome::Failed {
         break;
       }
     }
 
-    return Attempts::new(lock.into_inner().unwrap(), attempts);
+    let ci = lock.into_inner().unwrap();
+    let mut origins = Vec::new();
+    let mut merge_error = None;
+    if !layers.is_empty() {
+      let (value, merged) = merge::merge(policy, &layers);
+      match ci.try_value(&Box::new(value)) {
+        None => origins = merged,
+        Some(e) => {
+          // the item holds the value from the lowest-priority source; prefer the highest
+          ci.try_value(&Box::new(layers[0].0.clone()));
+          merge_error = Some(e);
+        }
+      }
+    }
+
+    let mut result = Attempts::new(ci, attempts);
+    result.origins = origins;
+    result.merge_error = merge_error;
+    result
   }
 }
 
@@ -209,7 +688,7 @@ mod test {
     let sources: Vec<&dyn ConfigurationValueSource> = vec![&env];
     let strategy = ConfigurationStrategy::new(sources);
     let mut ci = TestConfigurationItem::new(None);
-    let res = strategy.try_get(&mut ci);
+    let _res = strategy.try_get(&mut ci);
     assert_eq!(Some(&String::from("test_value")), ci.get());
   }
 
@@ -217,6 +696,27 @@ mod test {
     (MyThreshold f64)
   );
 
+  config!(
+    #[max = 10.0]
+    (MyBoundedThreshold f64)
+  );
+
+  #[test]
+  fn invalid_values_stop_the_search() {
+    let env = crate::environment::Environment::new("APPNAME".into(), vec![("APPNAME_MY_BOUNDED_THRESHOLD".into(), Ok("43.1".into()))]);
+    let cli = crate::command_line::CommandLine::from_args(vec!["prog".into(), "--my-bounded-threshold=4".into()]);
+    let sources: Vec<&dyn ConfigurationValueSource> = vec![&env, &cli];
+    let strategy = ConfigurationStrategy::new(sources);
+    let mut ci = MyBoundedThreshold::new(None);
+    let res = strategy.try_get(&mut ci);
+
+    let outcomes: Vec<Outcome> = res.attempts().iter().map(|a| a.outcome()).collect();
+    assert_eq!(outcomes, vec![Outcome::Invalid]);
+    let error = res.attempts()[0].error().unwrap();
+    assert_eq!(constraint::find_invalid(error).unwrap().to_string(), "Value 43.1 is invalid: it must be at most 10.0.");
+    assert_eq!(None, ci.get());
+  }
+
   #[test]
   fn tci_double() {
     let env = crate::environment::Environment::new("APPNAME".into(), vec![("APPNAME_MY_THRESHOLD".into(), Ok("43.1".into()))]);
@@ -228,6 +728,67 @@ mod test {
     assert_eq!(Some(&43.1f64), ci.get());
   }
 
+  config!(
+    #[merge(append)]
+    (AllowedOrigins Vec<String>),
+    #[max_length = 2]
+    #[merge(append)]
+    (FewOrigins Vec<String>)
+  );
+
+  #[test]
+  fn collections_can_be_merged_across_sources() {
+    let env = crate::environment::Environment::new("APPNAME".into(), vec![
+      ("APPNAME_ALLOWED_ORIGINS".into(), Ok("c".into())),
+      ("APPNAME_FEW_ORIGINS".into(), Ok("c".into())),
+    ]);
+    let file = crate::config_file::ConfigurationFile::from_str("config.toml", "allowed_origins = [\"a\", \"b\"]\nfew_origins = [\"a\", \"b\"]\n", crate::config_file::Format::Toml);
+    let sources: Vec<&dyn ConfigurationValueSource> = vec![&env, &file];
+    let strategy = ConfigurationStrategy::new(sources);
+
+    let mut ci = AllowedOrigins::new(None);
+    let res = strategy.try_get(&mut ci);
+    assert_eq!(res.provenance().unwrap(), "file config.toml key allowed_origins and env APPNAME_ALLOWED_ORIGINS");
+    assert_eq!(res.report(), concat!(
+      "allowed_origins:\n",
+      "  env APPNAME_ALLOWED_ORIGINS: accepted\n",
+      "  file config.toml key allowed_origins: accepted\n",
+      "  merged (append):\n",
+      "    [0] = \"a\" from file config.toml key allowed_origins\n",
+      "    [1] = \"b\" from file config.toml key allowed_origins\n",
+      "    [2] = \"c\" from env APPNAME_ALLOWED_ORIGINS\n",
+    ));
+    assert_eq!(ci.get(), Some(&vec![String::from("a"), String::from("b"), String::from("c")]));
+
+    // the merged value must still satisfy the item's constraints
+    let mut ci = FewOrigins::new(None);
+    let res = strategy.try_get(&mut ci);
+    assert!(res.merge_error().is_some());
+    assert_eq!(ci.get(), Some(&vec![String::from("c")]));
+  }
+
+  config!(
+    #[default = "http://proxy:3128"]
+    (HttpProxy String)
+  );
+
+  #[test]
+  fn explicitly_unset_items_skip_lower_sources() {
+    let cli = crate::command_line::CommandLine::from_args(vec!["prog".into(), "--no-http-proxy".into()]);
+    let file = crate::config_file::ConfigurationFile::from_str("config.json", r#"{"http_proxy": "http://other:3128"}"#, crate::config_file::Format::Json);
+    let defaults = crate::defaults::Defaults;
+    let sources: Vec<&dyn ConfigurationValueSource> = vec![&cli, &file, &defaults];
+    let strategy = ConfigurationStrategy::new(sources);
+
+    let mut ci = HttpProxy::new(None);
+    let res = strategy.try_get(&mut ci);
+    let outcomes: Vec<Outcome> = res.attempts().iter().map(|a| a.outcome()).collect();
+    assert_eq!(outcomes, vec![Outcome::Unset]);
+    assert_eq!(res.report(), "http_proxy:\n  command line --http-proxy: explicitly unset\n");
+    assert_eq!(res.provenance(), None);
+    assert_eq!(ci.get(), None);
+  }
+
   // struct TestConfigurationItem {
   //   value: Option<String>,
   // }
@@ -0,0 +1,212 @@
+//! Combining collection values provided by more than one source.
+//!
+//! By default the highest-priority source that provides a value wins, even for lists and maps.
+//! Items can instead declare a merge policy with `#[merge(append)]`, `#[merge(prepend)]` or
+//! `#[merge(deep)]`, in which case `ConfigurationStrategy` consults every source and combines the
+//! values they provide.
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::value::Value;
+
+/// How values from several sources are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Merge {
+  /// Use the value from the highest-priority source only.
+  #[default]
+  Replace,
+  /// Lists: elements from higher-priority sources are added after those from lower-priority ones.
+  Append,
+  /// Lists: elements from higher-priority sources are added before those from lower-priority ones.
+  Prepend,
+  /// Maps: entries are combined recursively, higher-priority sources win for each key.
+  Deep,
+}
+
+
+impl fmt::Display for Merge {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Merge::Replace => "replace",
+      Merge::Append => "append",
+      Merge::Prepend => "prepend",
+      Merge::Deep => "deep",
+    })
+  }
+}
+
+/// Types that `Merge::Append` and `Merge::Prepend` apply to. `config!` rejects those policies on
+/// items of other types:
+///
+/// ```compile_fail
+/// config::config!(
+///   #[merge(append)]
+///   (Name String)
+/// );
+/// ```
+pub trait Sequence {}
+
+impl<T> Sequence for Vec<T> {}
+impl<T> Sequence for HashSet<T> {}
+impl<T> Sequence for BTreeSet<T> {}
+
+/// Types that `Merge::Deep` applies to. `config!` rejects the policy on items of other types:
+///
+/// ```compile_fail
+/// config::config!(
+///   #[merge(deep)]
+///   (Hosts Vec<String>)
+/// );
+/// ```
+pub trait Table {}
+
+impl<T> Table for HashMap<String, T> {}
+impl<T> Table for BTreeMap<String, T> {}
+
+// referred to by `config!`, so that a policy on an item of the wrong type does not compile
+#[doc(hidden)]
+pub fn sequence<T: Sequence>() {}
+
+#[doc(hidden)]
+pub fn table<T: Table>() {}
+
+/// Records which source provided part of a merged value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+  /// The element, e.g. `[2]` for a list element or `team` (or `labels.team`) for a map entry.
+  pub element: String,
+  /// The value of the element.
+  pub value: Value,
+  /// The source that provided it, as returned by `ConfigurationValueSource::describe`.
+  pub source: String,
+}
+
+/// Combine `layers`, each a value and the description of the source that provided it, ordered from
+/// highest to lowest priority.
+/// Returns the merged value and where each of its elements came from.
+pub fn merge(policy: Merge, layers: &[(Value, String)]) -> (Value, Vec<Origin>) {
+  let mut origins = Vec::new();
+  let value = match policy {
+    Merge::Replace => match layers.first() {
+      Some((value, _)) => value.clone(),
+      None => return (Value::List(Vec::new()), origins),
+    },
+    Merge::Append | Merge::Prepend => {
+      let mut elements = Vec::new();
+      let mut ordered: Vec<&(Value, String)> = layers.iter().collect();
+      if policy == Merge::Append {
+        ordered.reverse();
+      }
+      for (value, source) in ordered {
+        match value {
+          Value::List(list) => elements.extend(list.iter().map(|v| (v.clone(), source))),
+          other => elements.push((other.clone(), source)),
+        }
+      }
+      for (i, (value, source)) in elements.iter().enumerate() {
+        origins.push(Origin { element: format!("[{}]", i), value: value.clone(), source: source.to_string() });
+      }
+      Value::List(elements.into_iter().map(|(v, _)| v).collect())
+    }
+    Merge::Deep => {
+      let mut merged: Option<Value> = None;
+      let mut from: BTreeMap<String, (Value, &str)> = BTreeMap::new();
+      for (value, source) in layers.iter().rev() {
+        merged = Some(match merged {
+          None => value.clone(),
+          Some(base) => deep(base, value),
+        });
+        note(&mut from, "", value, source);
+      }
+      for (element, (value, source)) in from {
+        origins.push(Origin { element: element, value: value, source: source.into() });
+      }
+      merged.unwrap_or(Value::Map(BTreeMap::new()))
+    }
+  };
+  (value, origins)
+}
+
+// overlay `top` onto `base`, merging maps key by key
+fn deep(base: Value, top: &Value) -> Value {
+  match (base, top) {
+    (Value::Map(mut base), Value::Map(top)) => {
+      for (key, value) in top {
+        let merged = match base.remove(key) {
+          Some(existing) => deep(existing, value),
+          None => value.clone(),
+        };
+        base.insert(key.clone(), merged);
+      }
+      Value::Map(base)
+    }
+    (_, top) => top.clone(),
+  }
+}
+
+// record the source of every leaf in `value`; later calls overwrite earlier ones
+fn note<'a>(from: &mut BTreeMap<String, (Value, &'a str)>, path: &str, value: &Value, source: &'a str) {
+  match value {
+    Value::Map(map) => for (key, value) in map {
+      let path = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+      // a scalar replacing a map (or vice versa) supersedes what was recorded for the old one
+      if let Value::Map(_) = value {
+        from.remove(&path);
+      }
+      else {
+        let prefix = format!("{}.", path);
+        from.retain(|k, _| !k.starts_with(&prefix));
+      }
+      note(from, &path, value, source);
+    },
+    other => { from.insert(path.into(), (other.clone(), source)); }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  fn list(items: &[&str]) -> Value {
+    Value::List(items.iter().map(|s| Value::String(s.to_string())).collect())
+  }
+
+  fn map(entries: Vec<(&str, Value)>) -> Value {
+    Value::Map(entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+  }
+
+  #[test]
+  fn append_and_prepend() {
+    let layers = vec![(list(&["b"]), "env".to_string()), (list(&["a"]), "file".to_string())];
+
+    let (value, origins) = merge(Merge::Append, &layers);
+    assert_eq!(value, list(&["a", "b"]));
+    assert_eq!(origins[1], Origin { element: "[1]".into(), value: Value::String("b".into()), source: "env".into() });
+
+    let (value, origins) = merge(Merge::Prepend, &layers);
+    assert_eq!(value, list(&["b", "a"]));
+    assert_eq!(origins[1].source, "file");
+  }
+
+  #[test]
+  fn deep_merges_nested_maps() {
+    let layers = vec![
+      (map(vec![("db", map(vec![("host", Value::String("prod".into()))]))]), "env".to_string()),
+      (map(vec![
+        ("db", map(vec![("host", Value::String("local".into())), ("port", Value::Integer(5432))])),
+        ("team", Value::String("x".into())),
+      ]), "file".to_string()),
+    ];
+
+    let (value, origins) = merge(Merge::Deep, &layers);
+    assert_eq!(value, map(vec![
+      ("db", map(vec![("host", Value::String("prod".into())), ("port", Value::Integer(5432))])),
+      ("team", Value::String("x".into())),
+    ]));
+    let sources: Vec<(&str, &str)> = origins.iter().map(|o| (o.element.as_str(), o.source.as_str())).collect();
+    assert_eq!(sources, vec![("db.host", "env"), ("db.port", "file"), ("team", "file")]);
+  }
+}
@@ -0,0 +1,145 @@
+//! Descriptive information about configuration items, used by help text, documentation, schema
+//! export and error messages.
+
+use std::error::Error;
+
+use crate::ConfigurationItem;
+use crate::constraint;
+use crate::value::Kind;
+use crate::value::Value;
+
+/// How prominently an item should be shown to users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Visibility {
+  /// Shown everywhere.
+  #[default]
+  Normal,
+  /// Shown, but marked as something most users do not need to change.
+  Advanced,
+  /// Still accepted, but left out of help text and documentation.
+  Hidden,
+}
+
+
+/// Descriptive information about a configuration item.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+  /// Human-readable description, usually taken from doc comments.
+  pub description: Option<String>,
+  /// Example values.
+  pub examples: Vec<Value>,
+  /// Units the value is measured in, e.g. "seconds".
+  pub units: Option<&'static str>,
+  /// The version of the application in which the item was introduced.
+  pub since: Option<&'static str>,
+  pub visibility: Visibility,
+  /// The value may change while the process runs, so sources must not cache it.
+  pub volatile: bool,
+}
+
+impl Metadata {
+  /// Append a line of description. Leading and trailing whitespace is removed.
+  pub fn push_description(&mut self, line: &str) {
+    let line = line.trim();
+    match &mut self.description {
+      None => self.description = Some(line.to_string()),
+      Some(d) => {
+        d.push('\n');
+        d.push_str(line);
+      }
+    }
+  }
+}
+
+/// Return a short sentence describing what kind of value the item expects, for use in messages
+/// about the error that made it reject a value. Values that were parsed, but did not satisfy the
+/// item's constraints, are described by the constraints they did not satisfy instead.
+pub fn hint(ci: &dyn ConfigurationItem, error: &(dyn Error + 'static)) -> String {
+  if let Some(invalid) = constraint::find_invalid(error) {
+    return format!("It {}.", invalid.violations.join(", and it "));
+  }
+
+  let metadata = ci.get_metadata();
+  let mut hint = match ci.get_kind() {
+    Kind::Enum(choices) => format!("Expected one of {}", choices.join(", ")),
+    kind => format!("Expected a value of type {}", kind.name()),
+  };
+
+  if let Some(units) = metadata.units {
+    hint.push_str(&format!(" (in {})", units));
+  }
+
+  if let Some(example) = metadata.examples.first() {
+    hint.push_str(&format!(", for example {}", example.to_json_inline()));
+  }
+
+  hint.push('.');
+  hint
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+
+  config!(
+    /// How long to wait for the database.
+    ///
+    /// Includes the time taken to connect.
+    #[units = "seconds"]
+    #[example = 30]
+    #[example = 90]
+    #[since = "1.2.0"]
+    #[advanced]
+    (MetadataTimeout u64),
+
+    #[hidden]
+    (MetadataUndocumented String),
+
+    #[example = vec![String::from("a"), String::from("b")]]
+    (MetadataHosts Vec<String>),
+
+    #[min = 1]
+    #[max = 10]
+    #[units = "retries"]
+    (MetadataRetries u32)
+  );
+
+  #[test]
+  fn attributes_become_metadata() {
+    let metadata = MetadataTimeout::new(None).get_metadata();
+    assert_eq!(metadata, Metadata {
+      description: Some("How long to wait for the database.\n\nIncludes the time taken to connect.".into()),
+      examples: vec![Value::Integer(30), Value::Integer(90)],
+      units: Some("seconds"),
+      since: Some("1.2.0"),
+      visibility: Visibility::Advanced,
+      volatile: false,
+    });
+
+    assert_eq!(MetadataUndocumented::new(None).get_metadata(), Metadata {
+      visibility: Visibility::Hidden,
+      ..Metadata::default()
+    });
+  }
+
+  #[test]
+  fn hint_uses_units_and_examples() {
+    assert_eq!(
+      hint(&MetadataTimeout::new(None), &crate::ValueNotHandled),
+      "Expected a value of type integer (in seconds), for example 30.");
+    assert_eq!(
+      hint(&MetadataUndocumented::new(None), &crate::ValueNotHandled),
+      "Expected a value of type string.");
+    assert_eq!(
+      hint(&MetadataHosts::new(None), &crate::ValueNotHandled),
+      "Expected a value of type list of string, for example [\"a\", \"b\"].");
+  }
+
+  #[test]
+  fn hint_shows_violated_constraints() {
+    let e = constraint::check_value(&20u32, &MetadataRetries::new(None).get_constraints()).unwrap_err();
+    assert_eq!(hint(&MetadataRetries::new(None), &e), "It must be at most 10.");
+  }
+}
@@ -0,0 +1,176 @@
+//! Items holding filesystem paths.
+//!
+//! A leading `~` is replaced by the user's home directory wherever the path comes from. Relative
+//! paths in configuration files are resolved against the directory containing the file, so
+//! `data = "cache"` in `/etc/myapp/config.toml` means `/etc/myapp/cache`; relative paths from the
+//! environment or the command line are left relative to the working directory.
+
+use std::env;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::value::ConfigurationValue;
+use crate::value::Kind;
+use crate::value::Literal;
+use crate::value::Value;
+
+/// Replace a leading `~` with the value of `HOME`, if it is set.
+/// `~user` is not expanded.
+pub fn expand_home<P: AsRef<Path>>(path: P) -> PathBuf {
+  let path = path.as_ref();
+  match (path.strip_prefix("~"), env::var_os("HOME")) {
+    (Ok(rest), Some(home)) if rest.as_os_str().is_empty() => PathBuf::from(home),
+    (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+    _ => path.to_path_buf(),
+  }
+}
+
+/// Returned when a path resolves to one that is not valid unicode, e.g. because the directory
+/// containing the configuration file is not, so it can't be passed on as text.
+#[derive(thiserror::Error, Debug)]
+#[error("Path ({path}) resolves to ({}), which is not valid unicode.", .resolved.display())]
+pub struct NotUnicode {
+  pub path: String,
+  pub resolved: PathBuf,
+}
+
+/// Expand `~` in, and resolve relative paths against `base`, every path in `value`.
+/// Values that are not paths (according to `kind`) are returned unchanged.
+pub fn resolve(value: &Value, kind: &Kind, base: &Path) -> Result<Value, NotUnicode> {
+  Ok(match (kind, value) {
+    (Kind::Path, Value::String(s)) => {
+      let resolved = base.join(expand_home(s));
+      match resolved.to_str() {
+        Some(r) => Value::String(r.into()),
+        None => return Err(NotUnicode { path: s.clone(), resolved: resolved }),
+      }
+    }
+    (Kind::List(inner), Value::List(items)) =>
+      Value::List(items.iter().map(|v| resolve(v, inner, base)).collect::<Result<_, _>>()?),
+    (Kind::Map(inner), Value::Map(map)) =>
+      Value::Map(map.iter().map(|(k, v)| Ok((k.clone(), resolve(v, inner, base)?))).collect::<Result<_, _>>()?),
+    _ => value.clone(),
+  })
+}
+
+/// A value whose paths are to be resolved against `base` once the `${...}` references in it are
+/// expanded, so that `${env:HOME}/data` is not made relative to `base`.
+/// Passed by `config_file::ConfigurationFile` to items that may expand references, see `interpolate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unresolved {
+  pub value: Value,
+  pub base: PathBuf,
+}
+
+/// Checks made against paths when they are loaded, see `constraint::Constraint::Path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+  Exists,
+  IsFile,
+  IsDirectory,
+  Readable,
+}
+
+impl Check {
+  pub fn describe(&self) -> &'static str {
+    match self {
+      Check::Exists => "must exist",
+      Check::IsFile => "must be a file",
+      Check::IsDirectory => "must be a directory",
+      Check::Readable => "must be readable",
+    }
+  }
+
+  pub fn is_satisfied_by(&self, path: &Path) -> bool {
+    match self {
+      Check::Exists => path.exists(),
+      Check::IsFile => path.is_file(),
+      Check::IsDirectory => path.is_dir(),
+      Check::Readable =>
+        if path.is_dir() { fs::read_dir(path).is_ok() }
+        else { fs::File::open(path).is_ok() },
+    }
+  }
+}
+
+impl ConfigurationValue for PathBuf {
+  fn kind() -> Kind { Kind::Path }
+  fn to_value(&self) -> Value { Value::String(self.to_string_lossy().into_owned()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Ok(expand_home(s)) }
+  fn from_os_str(s: &OsStr) -> Result<Self, Box<dyn Error>> { Ok(expand_home(s)) }
+  fn paths(&self) -> Vec<&Path> { vec![self] }
+}
+
+impl Literal<PathBuf> for &str {
+  fn convert(self) -> PathBuf { expand_home(self) }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+  use crate::ConfigurationItem;
+  use crate::testing::TempDir;
+
+  config!(
+    #[exists]
+    (PathData PathBuf),
+
+    #[default = "~/.cache/app"]
+    (PathCache PathBuf)
+  );
+
+  #[test]
+  fn home_is_expanded() {
+    let home = PathBuf::from(env::var_os("HOME").unwrap());
+    assert_eq!(expand_home("~"), home);
+    assert_eq!(expand_home("~/.config/app"), home.join(".config/app"));
+    assert_eq!(expand_home("~other/x"), PathBuf::from("~other/x"));
+    assert_eq!(expand_home("/tmp/~"), PathBuf::from("/tmp/~"));
+
+    let mut ci = PathCache::new(None);
+    assert!(ci.try_default().is_none());
+    assert_eq!(ci.get(), Some(&home.join(".cache/app")));
+  }
+
+  #[test]
+  fn relative_paths_resolve_against_the_base() {
+    let base = Path::new("/etc/myapp");
+    let kind = Kind::List(Box::new(Kind::Path));
+    let value = Value::List(vec![Value::String("cache".into()), Value::String("/var/lib/x".into())]);
+    assert_eq!(resolve(&value, &kind, base).unwrap(), Value::List(vec![
+      Value::String("/etc/myapp/cache".into()),
+      Value::String("/var/lib/x".into()),
+    ]));
+    assert_eq!(resolve(&Value::String("cache".into()), &Kind::String, base).unwrap(), Value::String("cache".into()));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn bases_that_are_not_unicode_are_rejected() {
+    use std::os::unix::ffi::OsStringExt;
+    let base = PathBuf::from(std::ffi::OsString::from_vec(b"/etc/caf\xe9".to_vec()));
+    let e = resolve(&Value::String("cache".into()), &Kind::Path, &base).unwrap_err();
+    assert_eq!(e.to_string(), "Path (cache) resolves to (/etc/caf\u{FFFD}/cache), which is not valid unicode.");
+    assert_eq!(resolve(&Value::String("/var/lib/x".into()), &Kind::Path, &base).unwrap(), Value::String("/var/lib/x".into()));
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn paths_that_are_not_unicode_are_checked_as_they_are() {
+    use std::os::unix::ffi::OsStringExt;
+    let parent = TempDir::new("path");
+    let dir = parent.join(std::ffi::OsString::from_vec(b"caf\xe9".to_vec()));
+    fs::create_dir(&dir).unwrap();
+
+    let mut ci = PathData::new(None);
+    assert!(ci.try_value(&Box::new(dir.clone().into_os_string())).is_none());
+    assert_eq!(ci.get(), Some(&dir));
+    fs::remove_dir(&dir).unwrap();
+    assert!(ci.try_value(&Box::new(dir.into_os_string())).is_some());
+  }
+}
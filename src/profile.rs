@@ -0,0 +1,211 @@
+//! Named profiles, e.g. `dev`, `staging` and `prod`, that add layers to a strategy.
+//!
+//! The profile is chosen by the `Profile` item (`APPNAME_PROFILE` or `--profile`). For a base
+//! file `config.toml` and the profile `prod`, `overlays` returns sources for `config.prod.toml` and
+//! for the `[profile.prod]` table in `config.toml`, which should be consulted before the base file.
+
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use derive_new::new;
+
+use crate::config;
+use crate::constraint;
+use crate::config_file::ConfigurationFile;
+use crate::ConfigurationItem;
+use crate::ConfigurationStrategy;
+use crate::ConfigurationValueSource;
+use crate::Outcome;
+
+config!(
+  /// The configuration profile to use, e.g. `prod`.
+  #[pattern = "^[A-Za-z0-9_-]+$"]
+  #[example = "prod"]
+  (Profile String)
+);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+  #[error("Profile from {from} was not accepted: {reason}")]
+  NotAccepted {
+    from: String,
+    reason: String,
+  },
+}
+
+/// The profile that was chosen, and how.
+#[derive(Debug, Clone)]
+pub struct Selection {
+  name: Option<String>,
+  provenance: Option<String>,
+  report: String,
+}
+
+impl Selection {
+  /// Return the name of the profile, if one was chosen.
+  pub fn name(&self) -> Option<&str> {
+    self.name.as_deref()
+  }
+
+  /// Describe where the profile came from, e.g. "env APPNAME_PROFILE".
+  pub fn provenance(&self) -> Option<&str> {
+    self.provenance.as_deref()
+  }
+
+  /// Explain how the profile was chosen, see `Attempts::report`.
+  pub fn report(&self) -> &str {
+    &self.report
+  }
+}
+
+impl fmt::Display for Selection {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match (&self.name, &self.provenance) {
+      (Some(name), Some(provenance)) => write!(f, "profile {} (from {})", name, provenance),
+      _ => f.write_str("no profile"),
+    }
+  }
+}
+
+/// Choose the profile using these sources, e.g. the environment and the command line.
+/// Returns an error if a source provided a profile name that is not valid, rather than falling
+/// back to no profile.
+pub fn select(sources: &[&dyn ConfigurationValueSource]) -> Result<Selection, Error> {
+  let strategy = ConfigurationStrategy::new(sources.to_vec());
+  let mut item = Profile::new(None);
+  let (provenance, report) = {
+    let attempts = strategy.try_get(&mut item);
+    if let Some(invalid) = attempts.attempts().iter().find(|a| a.outcome() == Outcome::Invalid) {
+      return Err(Error::NotAccepted {
+        from: invalid.source().describe(attempts.item()),
+        reason: invalid.error().and_then(constraint::find_invalid).map(|e| e.to_string()).unwrap_or_default(),
+      });
+    }
+    (attempts.provenance(), attempts.report())
+  };
+
+  Ok(Selection {
+    name: item.get().cloned(),
+    provenance: provenance,
+    report: report,
+  })
+}
+
+/// A source that only applies to a profile.
+/// Values it provides are described as coming from the profile, e.g.
+/// "profile prod: file config.prod.toml key port".
+#[derive(Debug, new)]
+pub struct Layer<S> {
+  profile: String,
+  source: S,
+}
+
+impl<S> Layer<S> {
+  pub fn profile(&self) -> &str {
+    &self.profile
+  }
+
+  pub fn source(&self) -> &S {
+    &self.source
+  }
+}
+
+impl<S: ConfigurationValueSource> ConfigurationValueSource for Layer<S> {
+  fn try_get<'c, 's: 'c>(&'s self, ci: &'c mut dyn ConfigurationItem) -> Option<Box<dyn std::error::Error>> {
+    self.source.try_get(ci)
+  }
+
+  fn describe(&self, ci: &dyn ConfigurationItem) -> String {
+    format!("profile {}: {}", self.profile, self.source.describe(ci))
+  }
+
+  fn is_secret(&self, ci: &dyn ConfigurationItem) -> bool {
+    self.source.is_secret(ci)
+  }
+}
+
+/// Return the path of the file for the profile, e.g. `config.prod.toml` for `config.toml`.
+pub fn file_path<P: AsRef<Path>>(base: P, profile: &str) -> PathBuf {
+  let base = base.as_ref();
+  let stem = base.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+  let name = match base.extension() {
+    Some(ext) => format!("{}.{}.{}", stem, profile, ext.to_string_lossy()),
+    None => format!("{}.{}", stem, profile),
+  };
+  base.with_file_name(name)
+}
+
+/// Return the layers for the chosen profile, highest priority first: the profile's own file, then
+/// the `[profile.<name>]` table in the base file.
+/// Returns nothing if no profile was chosen.
+pub fn overlays(selection: &Selection, base: &ConfigurationFile) -> Vec<Layer<ConfigurationFile>> {
+  match selection.name() {
+    None => Vec::new(),
+    Some(name) => vec![
+      Layer::new(name.into(), ConfigurationFile::new(file_path(base.path(), name))),
+      Layer::new(name.into(), base.section(&format!("profile.{}", name))),
+    ],
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::command_line::CommandLine;
+  use crate::config_file::Format;
+  use crate::environment::Environment;
+
+  #[test]
+  fn profile_files_sit_next_to_the_base_file() {
+    assert_eq!(file_path("/etc/myapp/config.toml", "prod"), PathBuf::from("/etc/myapp/config.prod.toml"));
+    assert_eq!(file_path("config", "dev"), PathBuf::from("config.dev"));
+  }
+
+  config!(
+    (Port u16),
+    #[group = "database"]
+    (Host String)
+  );
+
+  #[test]
+  fn profiles_add_layers() {
+    let env = Environment::new("APPNAME".into(), vec![("APPNAME_PROFILE".into(), Ok("dev".into()))]);
+    let cli = CommandLine::from_args(vec!["prog".into(), "--profile".into(), "prod".into()]);
+    let selection = select(&[&cli, &env]).unwrap();
+    assert_eq!(selection.to_string(), "profile prod (from command line --profile)");
+
+    let base = ConfigurationFile::from_str("/nonexistent/config.toml", r#"
+port = 8080
+
+[database]
+host = "localhost"
+
+[profile.prod.database]
+host = "db.internal"
+"#, Format::Toml);
+    let layers = overlays(&selection, &base);
+    let mut sources: Vec<&dyn ConfigurationValueSource> = layers.iter().map(|l| l as &dyn ConfigurationValueSource).collect();
+    sources.push(&base);
+    let strategy = ConfigurationStrategy::new(sources);
+
+    let mut host = Host::new(None);
+    let res = strategy.try_get(&mut host);
+    assert_eq!(res.provenance().unwrap(), "profile prod: file /nonexistent/config.toml key profile.prod.database.host");
+    drop(res);
+    assert_eq!(host.get(), Some(&String::from("db.internal")));
+
+    let mut port = Port::new(None);
+    let res = strategy.try_get(&mut port);
+    assert_eq!(res.provenance().unwrap(), "file /nonexistent/config.toml key port");
+    assert!(res.report().contains("  profile prod: file /nonexistent/config.prod.toml key port: not used: Could not read"));
+  }
+
+  #[test]
+  fn invalid_profiles_are_errors() {
+    let env = Environment::new("APPNAME".into(), vec![("APPNAME_PROFILE".into(), Ok("../prod".into()))]);
+    let e = select(&[&env]).unwrap_err();
+    assert_eq!(e.to_string(), "Profile from env APPNAME_PROFILE was not accepted: Value \"../prod\" is invalid: it must match the pattern \"^[A-Za-z0-9_-]+$\".");
+  }
+}
@@ -0,0 +1,189 @@
+//! Generate a commented sample configuration file listing every item.
+
+use crate::ConfigurationItem;
+use crate::dump;
+use crate::environment::Environment;
+use crate::metadata::Visibility;
+use crate::schema;
+use crate::value::Kind;
+use crate::value::Value;
+
+/// Formats a sample configuration can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Toml,
+  Yaml,
+  /// One `NAME=value` line per item, using the names read by `environment::Environment`.
+  Env,
+}
+
+// quoted when the text would otherwise be cut short or changed, as in a shell or a `.env` file
+fn env_value(value: &Value) -> String {
+  let text = match value {
+    Value::String(s) => s.clone(),
+    _ => value.to_json_inline(),
+  };
+  if !text.chars().any(|c| c.is_whitespace() || "#\"'\\$`".contains(c)) {
+    return text;
+  }
+  let escaped: String = text.chars()
+    .flat_map(|c| if "\"\\$`".contains(c) { vec!['\\', c] } else { vec![c] })
+    .collect();
+  format!("\"{}\"", escaped)
+}
+
+fn comment(out: &mut String, indent: &str, text: &str) {
+  for line in text.lines() {
+    if line.is_empty() { out.push_str(&format!("{}#\n", indent)); }
+    else { out.push_str(&format!("{}# {}\n", indent, line)); }
+  }
+}
+
+fn item(out: &mut String, env: &Environment, ci: &dyn ConfigurationItem, format: Format) {
+  let metadata = ci.get_metadata();
+  let indent = if format == Format::Yaml && ci.get_group().is_some() { "  " } else { "" };
+
+  out.push('\n');
+  if let Some(description) = &metadata.description {
+    comment(out, indent, description);
+  }
+
+  // secret defaults are left out, an example is used when there is no default
+  let (value, example) = match (ci.get_default(), metadata.examples.first()) {
+    (Some(_), _) if ci.is_secret() => (None, false),
+    (Some(default), _) => (Some(default), false),
+    (None, Some(example)) => (Some(example.clone()), true),
+    (None, None) => (None, false),
+  };
+
+  let mut details = match ci.get_kind() {
+    Kind::Enum(choices) => format!("Type: one of {}", choices.join(", ")),
+    kind => format!("Type: {}", kind.name()),
+  };
+  if let Some(units) = metadata.units {
+    details.push_str(&format!(", in {}", units));
+  }
+  if metadata.visibility == Visibility::Advanced {
+    details.push_str(" (advanced)");
+  }
+  if example {
+    details.push_str(". Example:");
+  }
+  comment(out, indent, &details);
+
+  let line = match format {
+    Format::Toml => format!("{} = {}", dump::key(&schema::property_name(ci)), value.as_ref().map(dump::toml_value).unwrap_or_default()),
+    Format::Yaml => format!("{}: {}", dump::key(&schema::property_name(ci)), value.as_ref().map(dump::yaml_value).unwrap_or_default()),
+    Format::Env => format!("{}={}", env.envvar_name(ci), value.as_ref().map(env_value).unwrap_or_default()),
+  };
+  out.push_str(&format!("{}#{}\n", indent, line.trim_end()));
+}
+
+/// Return a sample configuration file containing every item, grouped by `get_group()`.
+/// Every line is commented out, so the file can be used as-is and the defaults still apply.
+/// Hidden items are left out.
+pub fn sample(env: &Environment, items: &[&dyn ConfigurationItem], format: Format) -> String {
+  let items: Vec<&dyn ConfigurationItem> = items.iter()
+    .filter(|ci| ci.get_metadata().visibility != Visibility::Hidden)
+    .cloned()
+    .collect();
+
+  let mut groups: Vec<&str> = Vec::new();
+  for ci in items.iter() {
+    if let Some(group) = ci.get_group() {
+      if !groups.contains(&group) { groups.push(group); }
+    }
+  }
+
+  let mut out = String::from("# Sample configuration.\n");
+
+  for ci in items.iter().filter(|ci| ci.get_group().is_none()) {
+    item(&mut out, env, *ci, format);
+  }
+
+  for group in groups {
+    match format {
+      Format::Toml => out.push_str(&format!("\n[{}]\n", dump::key(group))),
+      Format::Yaml => out.push_str(&format!("\n{}:\n", dump::key(group))),
+      Format::Env => out.push_str(&format!("\n# [{}]\n", group)),
+    }
+    for ci in items.iter().filter(|ci| ci.get_group() == Some(group)) {
+      item(&mut out, env, *ci, format);
+    }
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+
+  config!(
+    /// How many splines to reticulate at once.
+    #[default = 4.0]
+    (MyThreshold f64),
+
+    #[group = "database"]
+    #[default = "localhost"]
+    (Host String),
+
+    #[group = "database"]
+    #[secret]
+    #[default = "hunter2"]
+    (Password String),
+
+    #[group = "database"]
+    #[units = "seconds"]
+    #[example = 30]
+    (Timeout u64)
+  );
+
+  fn render(format: Format) -> String {
+    let env = Environment::new("APPNAME".into(), Vec::new());
+    let (threshold, host, password, timeout) = (MyThreshold::new(None), Host::new(None), Password::new(None), Timeout::new(None));
+    sample(&env, &[&host, &threshold, &password, &timeout], format)
+  }
+
+  #[test]
+  fn toml_sample() {
+    assert_eq!(render(Format::Toml), "\
+# Sample configuration.
+
+# How many splines to reticulate at once.
+# Type: number
+#my_threshold = 4.0
+
+[database]
+
+# Type: string
+#host = \"localhost\"
+
+# Type: string
+#password =
+
+# Type: integer, in seconds. Example:
+#timeout = 30
+");
+  }
+
+  #[test]
+  fn env_sample() {
+    let out = render(Format::Env);
+    assert!(out.contains("\n#APPNAME_MY_THRESHOLD=4.0\n"));
+    assert!(out.contains("\n# [database]\n"));
+    assert!(out.contains("\n#APPNAME__database__HOST=localhost\n"));
+    assert!(!out.contains("hunter2"));
+
+    assert_eq!(env_value(&Value::String("db #1".into())), "\"db #1\"");
+    assert_eq!(env_value(&Value::String("$HOME/\"x\"".into())), "\"\\$HOME/\\\"x\\\"\"");
+  }
+
+  #[test]
+  fn yaml_sample_indents_groups() {
+    let out = render(Format::Yaml);
+    assert!(out.contains("\ndatabase:\n\n  # Type: string\n  #host: \"localhost\"\n"));
+  }
+}
@@ -0,0 +1,253 @@
+//! Export the set of configuration items as a JSON Schema document, describing the configuration
+//! file format.
+
+use std::collections::BTreeMap;
+
+use convert_case::Casing;
+use convert_case::Case::Snake;
+
+use crate::ConfigurationItem;
+use crate::constraint::Constraint;
+use crate::fragments;
+use crate::value::Kind;
+use crate::value::Value;
+
+/// Return the key used for the item in configuration files.
+pub fn property_name(ci: &dyn ConfigurationItem) -> String {
+  ci.get_name().to_case(Snake)
+}
+
+/// Return the full (dotted) path to the item in configuration files.
+pub fn file_key(ci: &dyn ConfigurationItem) -> String {
+  match ci.get_group() {
+    Some(group) => format!("{}.{}", group, property_name(ci)),
+    None => property_name(ci),
+  }
+}
+
+fn kind_schema(kind: &Kind) -> BTreeMap<String, Value> {
+  let mut schema = BTreeMap::new();
+  let json_type = match kind {
+    // "30s", "1MiB" and "50%" are strings, but plain numbers are accepted too
+    Kind::Duration | Kind::ByteSize | Kind::Percentage =>
+      Value::List(vec![Value::String("string".into()), Value::String("number".into())]),
+    _ => Value::String(kind.json_type().into()),
+  };
+  schema.insert("type".to_string(), json_type);
+  match kind {
+    Kind::List(inner) => { schema.insert("items".to_string(), Value::Map(kind_schema(inner))); }
+    Kind::Map(inner) => { schema.insert("additionalProperties".to_string(), Value::Map(kind_schema(inner))); }
+    Kind::Enum(choices) => {
+      schema.insert("enum".to_string(), Value::List(choices.iter().map(|c| Value::String(c.clone())).collect()));
+    }
+    _ => (),
+  }
+  schema
+}
+
+/// Return the schema for a single configuration item.
+pub fn item_schema(ci: &dyn ConfigurationItem) -> Value {
+  let mut schema = kind_schema(&ci.get_kind());
+
+  let kind = ci.get_kind();
+  let numeric = matches!(kind, Kind::Integer | Kind::Number);
+  // JSON Schema counts the characters of strings, the items of arrays and the properties of objects
+  let (min_length, max_length) = match kind {
+    Kind::List(_) => ("minItems", "maxItems"),
+    Kind::Map(_) => ("minProperties", "maxProperties"),
+    _ => ("minLength", "maxLength"),
+  };
+  for constraint in ci.get_constraints() {
+    let (keyword, value) = match constraint {
+      // checked against the filesystem, there is no schema keyword for these
+      Constraint::Path(_) => continue,
+      // durations, byte sizes and percentages are written as strings, which JSON Schema can't compare
+      Constraint::Minimum(_) | Constraint::Maximum(_) if !numeric => continue,
+      Constraint::Minimum(min) => ("minimum", min),
+      Constraint::Maximum(max) => ("maximum", max),
+      Constraint::MinLength(min) => (min_length, Value::Integer(min as i128)),
+      Constraint::MaxLength(max) => (max_length, Value::Integer(max as i128)),
+      Constraint::Pattern(pattern) => ("pattern", Value::String(pattern.into())),
+      Constraint::OneOf(values) => ("enum", Value::List(values)),
+      Constraint::NonEmpty => (min_length, Value::Integer(1)),
+    };
+    schema.insert(keyword.to_string(), value);
+  }
+
+  let metadata = ci.get_metadata();
+  if let Some(description) = metadata.description {
+    schema.insert("description".to_string(), Value::String(description));
+  }
+
+  if !metadata.examples.is_empty() {
+    schema.insert("examples".to_string(), Value::List(metadata.examples));
+  }
+
+  if let Some(default) = ci.get_default().filter(|_| !ci.is_secret()) {
+    schema.insert("default".to_string(), default);
+  }
+
+  Value::Map(schema)
+}
+
+fn object(properties: BTreeMap<String, Value>) -> BTreeMap<String, Value> {
+  let mut schema = BTreeMap::new();
+  schema.insert("type".to_string(), Value::String("object".into()));
+  schema.insert("properties".to_string(), Value::Map(properties));
+  schema.insert("additionalProperties".to_string(), Value::Boolean(false));
+  schema
+}
+
+/// Return a JSON Schema describing a configuration file containing the specified items.
+/// Items that belong to a group are nested in a table named after the group.
+pub fn schema(items: &[&dyn ConfigurationItem]) -> Value {
+  let mut properties = BTreeMap::new();
+  let mut groups: BTreeMap<String, BTreeMap<String, Value>> = BTreeMap::new();
+
+  for ci in items {
+    let target = match ci.get_group() {
+      None => &mut properties,
+      Some(group) => groups.entry(group.to_string()).or_default(),
+    };
+    target.insert(property_name(*ci), item_schema(*ci));
+  }
+
+  for (group, group_properties) in groups {
+    properties.insert(group, Value::Map(object(group_properties)));
+  }
+
+  // `[profile.<name>]` tables hold the same items as the file, see `profile`
+  let mut profiles = BTreeMap::new();
+  profiles.insert("type".to_string(), Value::String("object".into()));
+  profiles.insert("additionalProperties".to_string(), Value::Map(object(properties.clone())));
+
+  // other files to read, see `fragments`
+  let mut include = BTreeMap::new();
+  include.insert("type".to_string(), Value::List(vec![Value::String("string".into()), Value::String("array".into())]));
+  include.insert("items".to_string(), Value::Map(kind_schema(&Kind::String)));
+
+  properties.insert("profile".to_string(), Value::Map(profiles));
+  properties.insert(fragments::INCLUDE_KEY.to_string(), Value::Map(include));
+
+  let mut schema = object(properties);
+  schema.insert("$schema".to_string(), Value::String("http://json-schema.org/draft-07/schema#".into()));
+  Value::Map(schema)
+}
+
+/// Return a JSON Schema document describing a configuration file containing the specified items.
+pub fn json_schema(items: &[&dyn ConfigurationItem]) -> String {
+  schema(items).to_json()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+
+  config!(
+    /// How many splines to reticulate at once.
+    #[default = 4.0]
+    #[example = 8.0]
+    (SchemaThreshold f64),
+
+    #[group = "database"]
+    (SchemaHost String),
+
+    #[min = 1]
+    #[max = 65535]
+    (SchemaPort u16),
+
+    #[non_empty]
+    #[one_of("old", "new")]
+    (SchemaAlgorithm String),
+
+    #[non_empty]
+    (SchemaHosts Vec<String>),
+
+    #[secret]
+    #[default = "hunter2"]
+    (SchemaPassword String),
+
+    #[min = "1s"]
+    #[max = "1h"]
+    (SchemaTimeout std::time::Duration),
+
+    #[min_length = 2]
+    (SchemaDir std::path::PathBuf)
+  );
+
+  #[test]
+  fn items_become_properties() {
+    let threshold = SchemaThreshold::new(None);
+    let host = SchemaHost::new(None);
+    let schema = schema(&[&threshold, &host]);
+
+    let properties = match schema {
+      Value::Map(ref m) => match m.get("properties") {
+        Some(Value::Map(p)) => p.clone(),
+        other => panic!("expected properties, got {:?}", other),
+      },
+      other => panic!("expected an object, got {:?}", other),
+    };
+
+    assert_eq!(
+      properties.get("schema_threshold").unwrap().to_json(),
+      "{\n  \"default\": 4.0,\n  \"description\": \"How many splines to reticulate at once.\",\n  \"examples\": [\n    8.0\n  ],\n  \"type\": \"number\"\n}");
+
+    match properties.get("database") {
+      Some(Value::Map(db)) => assert!(db.get("properties").unwrap().to_json().contains("\"schema_host\"")),
+      other => panic!("expected the database group, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn constraints_become_keywords() {
+    assert_eq!(
+      item_schema(&SchemaPort::new(None)).to_json(),
+      "{\n  \"maximum\": 65535,\n  \"minimum\": 1,\n  \"type\": \"integer\"\n}");
+    assert_eq!(
+      item_schema(&SchemaAlgorithm::new(None)).to_json_inline(),
+      "{\"enum\": [\"old\", \"new\"], \"minLength\": 1, \"type\": \"string\"}");
+  }
+
+  #[test]
+  fn secrets_and_strings_are_not_misdescribed() {
+    assert_eq!(item_schema(&SchemaPassword::new(None)).to_json_inline(), "{\"type\": \"string\"}");
+    assert_eq!(item_schema(&SchemaTimeout::new(None)).to_json_inline(), "{\"type\": [\"string\", \"number\"]}");
+    assert_eq!(item_schema(&SchemaDir::new(None)).to_json_inline(), "{\"minLength\": 2, \"type\": \"string\"}");
+  }
+
+  #[test]
+  fn includes_and_profiles_are_allowed() {
+    let host = SchemaHost::new(None);
+    let schema = schema(&[&host]);
+    let properties = match schema {
+      Value::Map(ref m) => match m.get("properties") {
+        Some(Value::Map(p)) => p.clone(),
+        other => panic!("expected properties, got {:?}", other),
+      },
+      other => panic!("expected an object, got {:?}", other),
+    };
+
+    assert_eq!(
+      properties.get("include").unwrap().to_json_inline(),
+      "{\"items\": {\"type\": \"string\"}, \"type\": [\"string\", \"array\"]}");
+    let profile = properties.get("profile").unwrap().to_json_inline();
+    assert!(profile.starts_with("{\"additionalProperties\": {\"additionalProperties\": false, \"properties\": {\"database\""));
+  }
+
+  #[test]
+  fn lists_describe_their_items() {
+    assert_eq!(
+      item_schema(&SchemaHosts::new(None)).to_json_inline(),
+      "{\"items\": {\"type\": \"string\"}, \"minItems\": 1, \"type\": \"array\"}");
+  }
+
+  #[test]
+  fn document_declares_draft() {
+    let host = SchemaHost::new(None);
+    let doc = json_schema(&[&host]);
+    assert!(doc.contains("\"$schema\": \"http://json-schema.org/draft-07/schema#\""));
+  }
+}
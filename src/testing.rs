@@ -0,0 +1,43 @@
+//! Helpers shared by the tests.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// An empty directory under the system's temporary directory, deleted when dropped, so that it is
+/// cleaned up even if the test fails.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+  /// Create the directory. `name` must be unique among the tests, as they run in parallel; the
+  /// process id keeps concurrent test runs apart.
+  pub fn new(name: &str) -> Self {
+    let path = std::env::temp_dir().join(format!("config-{}-{}", name, std::process::id()));
+    // left over from a run that was killed
+    let _ = fs::remove_dir_all(&path);
+    fs::create_dir_all(&path).unwrap();
+    TempDir(path)
+  }
+}
+
+impl Deref for TempDir {
+  type Target = Path;
+
+  fn deref(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl AsRef<Path> for TempDir {
+  fn as_ref(&self) -> &Path {
+    &self.0
+  }
+}
+
+impl Drop for TempDir {
+  fn drop(&mut self) {
+    let _ = fs::remove_dir_all(&self.0);
+  }
+}
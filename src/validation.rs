@@ -0,0 +1,247 @@
+//! Rules spanning several configuration items, checked once every item has been resolved.
+
+use crate::Attempts;
+use crate::schema;
+use crate::value::Kind;
+use crate::value::Value;
+
+/// Read-only view of the resolved configuration, used by validation rules.
+#[derive(Debug)]
+pub struct Resolved<'r, 'a, 'b> {
+  results: &'r [Attempts<'a, 'b>],
+}
+
+impl<'r, 'a, 'b> Resolved<'r, 'a, 'b> {
+  pub fn new(results: &'r [Attempts<'a, 'b>]) -> Self {
+    Self { results: results }
+  }
+
+  fn find(&self, name: &str) -> Option<&'r Attempts<'a, 'b>> {
+    self.results.iter().find(|a| a.item().get_name() == name)
+  }
+
+  /// Return the value of the item with the specified name (as returned by `get_name`).
+  pub fn value(&self, name: &str) -> Option<Value> {
+    self.find(name).and_then(|a| a.item().get_value())
+  }
+
+  /// Return the kind of the item with the specified name, if it was resolved.
+  pub fn kind(&self, name: &str) -> Option<Kind> {
+    self.find(name).map(|a| a.item().get_kind())
+  }
+
+  /// Return `true` if the item with the specified name has a value.
+  pub fn is_set(&self, name: &str) -> bool {
+    self.value(name).is_some()
+  }
+
+  /// Describe the item, its value and where the value came from.
+  pub fn involve(&self, name: &str) -> Involved {
+    match self.find(name) {
+      None => Involved {
+        key: name.to_string(),
+        value: None,
+        provenance: String::from("not declared"),
+      },
+      Some(attempts) => {
+        let item = attempts.item();
+        Involved {
+          key: schema::file_key(item),
+          value: item.get_value().map(|v|
+            if attempts.is_secret() { Value::String(crate::dump::REDACTED.into()) } else { v }),
+          provenance: match attempts.provenance() {
+            Some(p) => format!("from {}", p),
+            None => String::from("not set"),
+          },
+        }
+      }
+    }
+  }
+}
+
+/// An item involved in a violated rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Involved {
+  pub key: String,
+  /// The value of the item, redacted if it is secret.
+  pub value: Option<Value>,
+  pub provenance: String,
+}
+
+impl std::fmt::Display for Involved {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match &self.value {
+      Some(v) => write!(f, "{} = {} ({})", self.key, v.to_json_inline(), self.provenance),
+      None => write!(f, "{} ({})", self.key, self.provenance),
+    }
+  }
+}
+
+/// A rule that was not satisfied by the resolved configuration.
+#[derive(thiserror::Error, Debug)]
+#[error("{message}: {}", .involved.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", "))]
+pub struct Violation {
+  pub message: String,
+  pub involved: Vec<Involved>,
+}
+
+type Check = dyn Fn(&Resolved) -> Result<(), String>;
+
+/// A rule spanning several items.
+pub struct Rule {
+  items: Vec<String>,
+  check: Box<Check>,
+}
+
+impl std::fmt::Debug for Rule {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    f.debug_struct("Rule").field("items", &self.items).finish()
+  }
+}
+
+/// Collection of rules to check against the resolved configuration.
+#[derive(Debug, Default)]
+pub struct Validators {
+  rules: Vec<Rule>,
+}
+
+impl Validators {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Add a rule involving the named items.
+  /// The check returns a message describing the problem if the rule is not satisfied.
+  pub fn rule<F>(&mut self, items: &[&str], check: F) -> &mut Self
+  where
+    F: Fn(&Resolved) -> Result<(), String> + 'static,
+  {
+    self.rules.push(Rule {
+      items: items.iter().map(|i| i.to_string()).collect(),
+      check: Box::new(check),
+    });
+    self
+  }
+
+  /// `item` must be set whenever the value of `condition` satisfies the predicate,
+  /// e.g. `TlsCert` is required when `TlsEnabled` is `true`.
+  pub fn required_if<P>(&mut self, item: &str, condition: &str, predicate: P) -> &mut Self
+  where
+    P: Fn(&Value) -> bool + 'static,
+  {
+    let (item_name, condition_name) = (item.to_string(), condition.to_string());
+    self.rule(&[item, condition], move |r| {
+      match r.value(&condition_name) {
+        Some(v) if predicate(&v) && !r.is_set(&item_name) => {
+          // the value is shown as `involve` shows it, i.e. redacted if it is secret
+          let condition = r.involve(&condition_name);
+          Err(format!("{} is required when {} is {}", r.involve(&item_name).key, condition.key, condition.value.unwrap_or(v).to_json_inline()))
+        }
+        _ => Ok(()),
+      }
+    })
+  }
+
+  /// The value of `lower` must not be greater than the value of `upper`, when both are set.
+  pub fn not_greater_than(&mut self, lower: &str, upper: &str) -> &mut Self {
+    let (lower_name, upper_name) = (lower.to_string(), upper.to_string());
+    self.rule(&[lower, upper], move |r| {
+      match (r.value(&lower_name), r.value(&upper_name)) {
+        (Some(l), Some(u)) if crate::constraint::compare(&l, &u, &r.kind(&lower_name).unwrap_or(Kind::String)) == Some(std::cmp::Ordering::Greater) =>
+          Err(format!("{} must not be greater than {}", r.involve(&lower_name).key, r.involve(&upper_name).key)),
+        _ => Ok(()),
+      }
+    })
+  }
+
+  /// Check every rule against the resolved configuration.
+  pub fn validate(&self, results: &[Attempts]) -> Result<(), Vec<Violation>> {
+    let resolved = Resolved::new(results);
+    let violations: Vec<Violation> = self.rules.iter()
+      .filter_map(|rule| match (rule.check)(&resolved) {
+        Ok(()) => None,
+        Err(message) => Some(Violation {
+          message: message,
+          involved: rule.items.iter().map(|i| resolved.involve(i)).collect(),
+        }),
+      })
+      .collect();
+
+    if violations.is_empty() { Ok(()) }
+    else { Err(violations) }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use crate::config;
+  use crate::ConfigurationStrategy;
+  use crate::ConfigurationValueSource;
+  use crate::defaults::Defaults;
+  use crate::environment::Environment;
+
+  config!(
+    #[default = false]
+    (TlsEnabled bool),
+    (TlsCert String),
+    #[default = 4]
+    (MinConnections u32),
+    #[default = 16]
+    (MaxConnections u32)
+  );
+
+  #[test]
+  fn violations_name_involved_items() {
+    let env = Environment::new("APPNAME".into(), vec![
+      ("APPNAME_TLS_ENABLED".into(), Ok("true".into())),
+      ("APPNAME_MIN_CONNECTIONS".into(), Ok("32".into())),
+    ]);
+    let defaults = Defaults;
+    let sources: Vec<&dyn ConfigurationValueSource> = vec![&env, &defaults];
+    let strategy = ConfigurationStrategy::new(sources);
+
+    let (mut enabled, mut cert, mut min, mut max) =
+      (TlsEnabled::new(None), TlsCert::new(None), MinConnections::new(None), MaxConnections::new(None));
+    let results = vec![
+      strategy.try_get(&mut enabled),
+      strategy.try_get(&mut cert),
+      strategy.try_get(&mut min),
+      strategy.try_get(&mut max),
+    ];
+
+    let mut validators = Validators::new();
+    validators
+      .required_if("TlsCert", "TlsEnabled", |v| v == &Value::Boolean(true))
+      .not_greater_than("MinConnections", "MaxConnections");
+
+    let violations: Vec<String> = validators.validate(&results).unwrap_err().iter().map(|v| v.to_string()).collect();
+    assert_eq!(violations, vec![
+      "tls_cert is required when tls_enabled is true: tls_cert (not set), tls_enabled = true (from env APPNAME_TLS_ENABLED)",
+      "min_connections must not be greater than max_connections: min_connections = 32 (from env APPNAME_MIN_CONNECTIONS), max_connections = 16 (from default)",
+    ]);
+  }
+
+  config!(
+    #[secret]
+    (AuthMode String)
+  );
+
+  #[test]
+  fn secret_conditions_are_not_shown() {
+    let env = Environment::new("APPNAME".into(), vec![("APPNAME_AUTH_MODE".into(), Ok("mtls".into()))]);
+    let strategy = ConfigurationStrategy::new(vec![&env]);
+
+    let (mut mode, mut cert) = (AuthMode::new(None), TlsCert::new(None));
+    let results = vec![strategy.try_get(&mut mode), strategy.try_get(&mut cert)];
+
+    let mut validators = Validators::new();
+    validators.required_if("TlsCert", "AuthMode", |v| v == &Value::String("mtls".into()));
+
+    let violations: Vec<String> = validators.validate(&results).unwrap_err().iter().map(|v| v.to_string()).collect();
+    assert_eq!(violations, vec![
+      "tls_cert is required when auth_mode is \"<redacted>\": tls_cert (not set), auth_mode = \"<redacted>\" (from env APPNAME_AUTH_MODE)",
+    ]);
+  }
+}
@@ -0,0 +1,515 @@
+//! Format-neutral representation of configuration values.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::hash::Hash;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The shape of the values a configuration item accepts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+  String,
+  Integer,
+  Number,
+  Boolean,
+  /// e.g. "1h30m", see `human::parse_duration`.
+  Duration,
+  /// e.g. "10MiB", see `human::parse_byte_size`.
+  ByteSize,
+  /// e.g. "50%", see `human::parse_percentage`.
+  Percentage,
+  /// A list of values of the inner kind.
+  List(Box<Kind>),
+  /// A map from strings to values of the inner kind.
+  Map(Box<Kind>),
+  /// A filesystem path, see `path`.
+  Path,
+  /// One of a fixed set of choices, see `choice`.
+  Enum(Vec<String>),
+}
+
+impl Kind {
+  /// Return the name of this kind as used by JSON Schema.
+  pub fn json_type(&self) -> &'static str {
+    use self::Kind::*;
+    match self {
+      String | Duration | ByteSize | Percentage | Path | Enum(_) => "string",
+      Integer => "integer",
+      Number => "number",
+      Boolean => "boolean",
+      List(_) => "array",
+      Map(_) => "object",
+    }
+  }
+
+  /// Return the name of this kind for use in documentation and messages.
+  pub fn name(&self) -> String {
+    use self::Kind::*;
+    match self {
+      Duration => "duration".into(),
+      ByteSize => "byte size".into(),
+      Percentage => "percentage".into(),
+      Path => "path".into(),
+      Enum(_) => "choice".into(),
+      List(inner) => format!("list of {}", inner.name()),
+      Map(inner) => format!("map of {}", inner.name()),
+      _ => self.json_type().into(),
+    }
+  }
+
+  /// Return `true` for lists and maps.
+  pub fn is_collection(&self) -> bool {
+    matches!(self, Kind::List(_) | Kind::Map(_))
+  }
+}
+
+/// A configuration value, independent of the type of the item that holds it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  String(String),
+  Integer(i128),
+  Number(f64),
+  Boolean(bool),
+  List(Vec<Value>),
+  Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+  /// Return the name of the kind of this value, for use in messages.
+  pub fn kind_name(&self) -> &'static str {
+    use self::Value::*;
+    match self {
+      String(_) => "string",
+      Integer(_) => "integer",
+      Number(_) => "number",
+      Boolean(_) => "boolean",
+      List(_) => "list",
+      Map(_) => "map",
+    }
+  }
+
+  /// Return scalar values as the string they would have been written as in e.g. an environment
+  /// variable.
+  pub fn as_scalar_string(&self) -> Option<std::string::String> {
+    use self::Value::*;
+    match self {
+      String(s) => Some(s.clone()),
+      Integer(i) => Some(i.to_string()),
+      Number(n) => Some(n.to_string()),
+      Boolean(b) => Some(b.to_string()),
+      List(_) | Map(_) => None,
+    }
+  }
+
+  /// Render this value as (pretty-printed) JSON.
+  pub fn to_json(&self) -> String {
+    let mut out = String::new();
+    self.write_json(&mut out, 0);
+    out
+  }
+
+  /// Render this value as JSON on a single line.
+  pub fn to_json_inline(&self) -> String {
+    use self::Value::*;
+    match self {
+      List(items) => format!("[{}]", items.iter().map(|v| v.to_json_inline()).collect::<Vec<_>>().join(", ")),
+      Map(entries) => format!("{{{}}}", entries.iter()
+        .map(|(k, v)| format!("{}: {}", json_string(k), v.to_json_inline()))
+        .collect::<Vec<_>>()
+        .join(", ")),
+      _ => self.to_json(),
+    }
+  }
+
+  fn write_json(&self, out: &mut String, indent: usize) {
+    use self::Value::*;
+    match self {
+      String(s) => out.push_str(&json_string(s)),
+      Integer(i) => out.push_str(&i.to_string()),
+      Number(n) => {
+        if n.is_finite() { out.push_str(&format!("{:?}", n)) }
+        else { out.push_str("null") }}
+      Boolean(b) => out.push_str(&b.to_string()),
+      List(items) => {
+        if items.is_empty() { out.push_str("[]"); return; }
+        out.push_str("[\n");
+        for (i, item) in items.iter().enumerate() {
+          out.push_str(&"  ".repeat(indent + 1));
+          item.write_json(out, indent + 1);
+          if i + 1 < items.len() { out.push(','); }
+          out.push('\n');
+        }
+        out.push_str(&"  ".repeat(indent));
+        out.push(']');
+      }
+      Map(entries) => {
+        if entries.is_empty() { out.push_str("{}"); return; }
+        out.push_str("{\n");
+        for (i, (k, v)) in entries.iter().enumerate() {
+          out.push_str(&"  ".repeat(indent + 1));
+          out.push_str(&json_string(k));
+          out.push_str(": ");
+          v.write_json(out, indent + 1);
+          if i + 1 < entries.len() { out.push(','); }
+          out.push('\n');
+        }
+        out.push_str(&"  ".repeat(indent));
+        out.push('}');
+      }
+    }
+  }
+}
+
+/// Quote and escape a string for use in JSON.
+pub fn json_string(s: &str) -> String {
+  let mut out = String::with_capacity(s.len() + 2);
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+/// Quote a string provided by the operating system, escaping any bytes that are not valid unicode,
+/// e.g. `"caf\xE9"`.
+pub fn quote_os(s: &OsStr) -> String {
+  let mut out = String::from("\"");
+  let mut bytes = s.as_encoded_bytes();
+  while !bytes.is_empty() {
+    let (valid, invalid) = match std::str::from_utf8(bytes) {
+      Ok(valid) => (valid, &bytes[bytes.len()..]),
+      Err(e) => {
+        let end = e.valid_up_to() + e.error_len().unwrap_or(bytes.len() - e.valid_up_to());
+        (std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(), &bytes[e.valid_up_to()..end])
+      }
+    };
+    let quoted = format!("{:?}", valid);
+    out.push_str(&quoted[1..quoted.len() - 1]);
+    out.push_str(&escape_bytes(invalid));
+    bytes = &bytes[valid.len() + invalid.len()..];
+  }
+  out.push('"');
+  out
+}
+
+fn escape_bytes(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("\\x{:02X}", b)).collect()
+}
+
+fn invalid_bytes(s: &OsStr) -> Vec<u8> {
+  let mut invalid = Vec::new();
+  let mut bytes = s.as_encoded_bytes();
+  while let Err(e) = std::str::from_utf8(bytes) {
+    let end = e.valid_up_to() + e.error_len().unwrap_or(bytes.len() - e.valid_up_to());
+    invalid.extend_from_slice(&bytes[e.valid_up_to()..end]);
+    bytes = &bytes[end..];
+  }
+  invalid
+}
+
+/// Types that can be held by a configuration item.
+pub trait ConfigurationValue {
+  /// Return the kind of value this type represents.
+  fn kind() -> Kind;
+
+  /// Return the format-neutral representation of this value.
+  fn to_value(&self) -> Value;
+
+  /// Parse a value of this type from a string, as provided by e.g. an environment variable.
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> where Self: Sized;
+
+  /// Convert from a string provided by the operating system, which may not be valid unicode, as
+  /// provided by e.g. an environment variable.
+  /// By default, the string must be valid unicode and is parsed.
+  fn from_os_str(s: &OsStr) -> Result<Self, Box<dyn Error>> where Self: Sized {
+    match s.to_str() {
+      Some(s) => Self::parse(s),
+      None => Err(Box::new(ValueError::NotUnicode { value: quote_os(s), invalid: invalid_bytes(s) })),
+    }
+  }
+
+  /// Return the paths this value holds, which `#[exists]` and the like check as they are rather
+  /// than as (possibly lossy) text. None by default.
+  fn paths(&self) -> Vec<&Path> { Vec::new() }
+
+  /// Convert from the format-neutral representation, as provided by e.g. a configuration file.
+  /// By default, scalars are converted to a string and parsed.
+  fn from_value(value: &Value) -> Result<Self, Box<dyn Error>> where Self: Sized {
+    match value.as_scalar_string() {
+      Some(s) => Self::parse(&s),
+      None => Err(Box::new(ValueError::ExpectedSingleValue { found: value.kind_name() })),
+    }
+  }
+}
+
+/// Describes why a value could not be converted.
+#[derive(thiserror::Error, Debug)]
+pub enum ValueError {
+  #[error("Expected a single value, found a {found}.")]
+  ExpectedSingleValue {
+    found: &'static str,
+  },
+  #[error("Expected a list, found a {found}.")]
+  ExpectedList {
+    found: &'static str,
+  },
+  #[error("Expected a map, found a {found}.")]
+  ExpectedMap {
+    found: &'static str,
+  },
+  #[error("Expected text, but {value} is not valid unicode (invalid bytes: {}).", escape_bytes(.invalid))]
+  NotUnicode {
+    value: std::string::String,
+    invalid: Vec<u8>,
+  },
+  #[error("Expected \"key=value\", found {entry:?}.")]
+  MalformedEntry {
+    entry: std::string::String,
+  },
+  #[error("Element {index} was not accepted: {source}")]
+  Element {
+    index: usize,
+    source: Box<dyn Error>,
+  },
+  #[error("Entry {key:?} was not accepted: {source}")]
+  Entry {
+    key: std::string::String,
+    source: Box<dyn Error>,
+  },
+}
+
+/// Separates the elements of lists (and the entries of maps) written as a single string.
+pub const DELIMITER: char = ',';
+
+/// Split a string into list elements, e.g. "a,b,c".
+pub fn split_list(s: &str, delimiter: char) -> Vec<Value> {
+  if s.trim().is_empty() { return Vec::new(); }
+  s.split(delimiter).map(|e| Value::String(e.trim().to_string())).collect()
+}
+
+/// Split a string into map entries, e.g. "team=x,env=prod".
+pub fn split_map(s: &str, delimiter: char) -> Result<BTreeMap<String, Value>, ValueError> {
+  let mut map = BTreeMap::new();
+  for entry in split_list(s, delimiter) {
+    let entry = entry.as_scalar_string().unwrap_or_default();
+    match entry.find('=') {
+      Some(i) => { map.insert(entry[..i].trim().to_string(), Value::String(entry[i + 1..].trim().to_string())); }
+      None => return Err(ValueError::MalformedEntry { entry: entry }),
+    }
+  }
+  Ok(map)
+}
+
+fn elements<T: ConfigurationValue>(value: &Value) -> Result<Vec<T>, Box<dyn Error>> {
+  match value {
+    Value::List(items) => items.iter().enumerate()
+      .map(|(i, v)| T::from_value(v).map_err(|e| Box::new(ValueError::Element { index: i, source: e }) as Box<dyn Error>))
+      .collect(),
+    other => Err(Box::new(ValueError::ExpectedList { found: other.kind_name() })),
+  }
+}
+
+fn entries<T: ConfigurationValue>(value: &Value) -> Result<Vec<(String, T)>, Box<dyn Error>> {
+  match value {
+    Value::Map(map) => map.iter()
+      .map(|(k, v)| match T::from_value(v) {
+        Ok(t) => Ok((k.clone(), t)),
+        Err(e) => Err(Box::new(ValueError::Entry { key: k.clone(), source: e }) as Box<dyn Error>),
+      })
+      .collect(),
+    other => Err(Box::new(ValueError::ExpectedMap { found: other.kind_name() })),
+  }
+}
+
+/// Parse a value using its `FromStr` implementation.
+/// Useful when implementing `ConfigurationValue` for your own types.
+pub fn parse_from_str<T>(s: &str) -> Result<T, Box<dyn Error>>
+where
+  T: FromStr,
+  T::Err: Error + 'static,
+{
+  match T::from_str(s) {
+    Ok(t) => Ok(t),
+    Err(e) => Err(Box::new(e)),
+  }
+}
+
+/// Conversion applied to the values written in `config!` attributes, such as defaults and examples.
+/// Lets `#[default = "text"]` be used for `String` items.
+pub trait Literal<T> {
+  fn convert(self) -> T;
+}
+
+impl<T> Literal<T> for T {
+  fn convert(self) -> T { self }
+}
+
+impl Literal<String> for &str {
+  fn convert(self) -> String { self.to_string() }
+}
+
+macro_rules! configuration_value {
+  ($kind:ident, $variant:ident, $($type:ty),*) => {
+    $(
+      impl ConfigurationValue for $type {
+        fn kind() -> Kind { Kind::$kind }
+        fn to_value(&self) -> Value { Value::$variant((*self).into()) }
+        fn parse(s: &str) -> Result<Self, Box<dyn Error>> { parse_from_str(s) }
+      }
+    )*
+  };
+}
+
+configuration_value!(Integer, Integer, i8, i16, i32, i64, u8, u16, u32, u64);
+configuration_value!(Number, Number, f32, f64);
+
+impl ConfigurationValue for bool {
+  fn kind() -> Kind { Kind::Boolean }
+  fn to_value(&self) -> Value { Value::Boolean(*self) }
+  /// Booleans are parsed leniently, see `human::parse_bool`.
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Ok(crate::human::parse_bool(s)?) }
+}
+
+impl ConfigurationValue for isize {
+  fn kind() -> Kind { Kind::Integer }
+  fn to_value(&self) -> Value { Value::Integer(*self as i128) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { parse_from_str(s) }
+}
+
+impl ConfigurationValue for usize {
+  fn kind() -> Kind { Kind::Integer }
+  fn to_value(&self) -> Value { Value::Integer(*self as i128) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { parse_from_str(s) }
+}
+
+impl ConfigurationValue for char {
+  fn kind() -> Kind { Kind::String }
+  fn to_value(&self) -> Value { Value::String(self.to_string()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { parse_from_str(s) }
+}
+
+impl ConfigurationValue for String {
+  fn kind() -> Kind { Kind::String }
+  fn to_value(&self) -> Value { Value::String(self.clone()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Ok(s.to_string()) }
+}
+
+/// Holds any string the operating system provides, including those that are not valid unicode.
+impl ConfigurationValue for OsString {
+  fn kind() -> Kind { Kind::String }
+  fn to_value(&self) -> Value { Value::String(self.to_string_lossy().into_owned()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Ok(OsString::from(s)) }
+  fn from_os_str(s: &OsStr) -> Result<Self, Box<dyn Error>> { Ok(s.to_os_string()) }
+}
+
+impl<T: ConfigurationValue> ConfigurationValue for Vec<T> {
+  fn kind() -> Kind { Kind::List(Box::new(T::kind())) }
+  fn to_value(&self) -> Value { Value::List(self.iter().map(T::to_value).collect()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Self::from_value(&Value::List(split_list(s, DELIMITER))) }
+  fn from_value(value: &Value) -> Result<Self, Box<dyn Error>> { elements(value) }
+  fn paths(&self) -> Vec<&Path> { self.iter().flat_map(T::paths).collect() }
+}
+
+impl<T: ConfigurationValue + Eq + Hash> ConfigurationValue for HashSet<T> {
+  fn kind() -> Kind { Kind::List(Box::new(T::kind())) }
+  fn to_value(&self) -> Value {
+    // sorted, so that the output is stable
+    let mut items: Vec<Value> = self.iter().map(T::to_value).collect();
+    items.sort_by_key(|v| v.to_json_inline());
+    Value::List(items)
+  }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Self::from_value(&Value::List(split_list(s, DELIMITER))) }
+  fn from_value(value: &Value) -> Result<Self, Box<dyn Error>> { Ok(elements(value)?.into_iter().collect()) }
+  fn paths(&self) -> Vec<&Path> { self.iter().flat_map(T::paths).collect() }
+}
+
+impl<T: ConfigurationValue + Ord> ConfigurationValue for BTreeSet<T> {
+  fn kind() -> Kind { Kind::List(Box::new(T::kind())) }
+  fn to_value(&self) -> Value { Value::List(self.iter().map(T::to_value).collect()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Self::from_value(&Value::List(split_list(s, DELIMITER))) }
+  fn from_value(value: &Value) -> Result<Self, Box<dyn Error>> { Ok(elements(value)?.into_iter().collect()) }
+  fn paths(&self) -> Vec<&Path> { self.iter().flat_map(T::paths).collect() }
+}
+
+impl<T: ConfigurationValue> ConfigurationValue for HashMap<String, T> {
+  fn kind() -> Kind { Kind::Map(Box::new(T::kind())) }
+  fn to_value(&self) -> Value { Value::Map(self.iter().map(|(k, v)| (k.clone(), v.to_value())).collect()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Self::from_value(&Value::Map(split_map(s, DELIMITER)?)) }
+  fn from_value(value: &Value) -> Result<Self, Box<dyn Error>> { Ok(entries(value)?.into_iter().collect()) }
+  fn paths(&self) -> Vec<&Path> { self.values().flat_map(T::paths).collect() }
+}
+
+impl<T: ConfigurationValue> ConfigurationValue for BTreeMap<String, T> {
+  fn kind() -> Kind { Kind::Map(Box::new(T::kind())) }
+  fn to_value(&self) -> Value { Value::Map(self.iter().map(|(k, v)| (k.clone(), v.to_value())).collect()) }
+  fn parse(s: &str) -> Result<Self, Box<dyn Error>> { Self::from_value(&Value::Map(split_map(s, DELIMITER)?)) }
+  fn from_value(value: &Value) -> Result<Self, Box<dyn Error>> { Ok(entries(value)?.into_iter().collect()) }
+  fn paths(&self) -> Vec<&Path> { self.values().flat_map(T::paths).collect() }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn lists_from_strings_and_values() {
+    assert_eq!(<Vec<u16>>::parse("1, 2,3").unwrap(), vec![1, 2, 3]);
+    assert_eq!(<Vec<u16>>::parse("").unwrap(), Vec::<u16>::new());
+
+    let value = Value::List(vec![Value::Integer(1), Value::String("two".into())]);
+    assert_eq!(
+      <Vec<u16>>::from_value(&value).unwrap_err().to_string(),
+      "Element 1 was not accepted: invalid digit found in string");
+  }
+
+  #[test]
+  fn maps_from_strings_and_values() {
+    let labels = <HashMap<String, String>>::parse("team=x, env=prod").unwrap();
+    assert_eq!(labels.get("team"), Some(&String::from("x")));
+    assert_eq!(labels.get("env"), Some(&String::from("prod")));
+
+    assert_eq!(
+      <HashMap<String, String>>::parse("team").unwrap_err().to_string(),
+      "Expected \"key=value\", found \"team\".");
+
+    let mut map = BTreeMap::new();
+    map.insert("a".to_string(), Value::String("x".into()));
+    assert_eq!(
+      <BTreeMap<String, u8>>::from_value(&Value::Map(map)).unwrap_err().to_string(),
+      "Entry \"a\" was not accepted: invalid digit found in string");
+  }
+
+  #[test]
+  fn json_escapes_strings() {
+    let v = Value::String("a \"quoted\"\nline".into());
+    assert_eq!(v.to_json(), r#""a \"quoted\"\nline""#);
+  }
+
+  #[test]
+  fn json_nests_maps() {
+    let mut inner = BTreeMap::new();
+    inner.insert("b".to_string(), Value::Number(4.0));
+    let mut outer = BTreeMap::new();
+    outer.insert("a".to_string(), Value::Map(inner));
+    outer.insert("c".to_string(), Value::List(vec![Value::Integer(1), Value::Boolean(true)]));
+
+    assert_eq!(
+      Value::Map(outer).to_json(),
+      "{\n  \"a\": {\n    \"b\": 4.0\n  },\n  \"c\": [\n    1,\n    true\n  ]\n}");
+  }
+}